@@ -0,0 +1,4 @@
+-- Add status column to subscriptions
+-- Rows inserted before the confirmation flow existed are considered confirmed
+ALTER TABLE subscriptions ADD COLUMN status TEXT NOT NULL DEFAULT 'confirmed';
+ALTER TABLE subscriptions ALTER COLUMN status DROP DEFAULT;
@@ -0,0 +1,9 @@
+-- Create Issue Delivery Queue Table
+-- One row per pending delivery, removed once the email has been handed to the provider
+CREATE TABLE issue_delivery_queue(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+   subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+   n_retries INT NOT NULL DEFAULT 0,
+   execute_after timestamptz NOT NULL DEFAULT now(),
+   PRIMARY KEY (newsletter_issue_id, subscriber_id)
+);
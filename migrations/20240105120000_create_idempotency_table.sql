@@ -0,0 +1,17 @@
+-- Create Idempotency Table
+CREATE TYPE header_pair AS (
+   name TEXT,
+   value BYTEA
+);
+
+-- Requests made without a logged-in user are stored under the nil uuid
+CREATE TABLE idempotency(
+   user_id uuid NOT NULL,
+   idempotency_key TEXT NOT NULL,
+   -- Response columns stay NULL while the first request is still being processed
+   response_status_code SMALLINT NULL,
+   response_headers header_pair[] NULL,
+   response_body BYTEA NULL,
+   created_at timestamptz NOT NULL,
+   PRIMARY KEY (user_id, idempotency_key)
+);
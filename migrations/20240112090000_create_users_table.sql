@@ -0,0 +1,8 @@
+-- Create Users Table
+-- Passwords are stored as Argon2id hashes in PHC string format
+CREATE TABLE users(
+   user_id uuid NOT NULL,
+   username TEXT NOT NULL UNIQUE,
+   password_hash TEXT NOT NULL,
+   PRIMARY KEY (user_id)
+);
@@ -0,0 +1,8 @@
+-- Seed the initial admin user
+-- Password is 'everythinghastostartsomewhere', it must be changed after the first login
+INSERT INTO users (user_id, username, password_hash)
+VALUES (
+   'ddf8994f-d522-4659-8d02-c1d479057be6',
+   'admin',
+   '$argon2id$v=19$m=15000,t=2,p=1$HP1N8GoTaFoXho9bHr2Yog$q3L+2uSWaiRbn0iMPMkByCZ4TuYh4eStWq7H0Wqtqf0'
+);
@@ -0,0 +1,10 @@
+-- Create Sessions Table
+-- user_id is copied out of the session state so that every session of a user can be found
+CREATE TABLE sessions(
+   session_key TEXT NOT NULL,
+   session_state JSONB NOT NULL,
+   user_id uuid NULL REFERENCES users (user_id) ON DELETE CASCADE,
+   expires_at timestamptz NOT NULL,
+   PRIMARY KEY (session_key)
+);
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,18 @@
+-- Create Delivery Events Table
+-- Bounces and spam complaints reported by the email provider. The raw payload is kept
+-- so nothing the provider told us is lost
+CREATE TABLE delivery_events(
+   id uuid NOT NULL,
+   -- Postmark's own id for the event, deliveries of the same webhook are recorded once
+   provider_event_id BIGINT NULL UNIQUE,
+   subscriber_id uuid NULL REFERENCES subscriptions (id),
+   email TEXT NOT NULL,
+   event_type TEXT NOT NULL,
+   bounce_type TEXT NULL,
+   message_id TEXT NULL,
+   payload JSONB NOT NULL,
+   occurred_at timestamptz NOT NULL,
+   received_at timestamptz NOT NULL,
+   PRIMARY KEY (id)
+);
+CREATE INDEX delivery_events_subscriber_id_idx ON delivery_events (subscriber_id);
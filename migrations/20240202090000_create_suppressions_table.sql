@@ -0,0 +1,32 @@
+-- Create Suppressions Table
+-- Addresses we must never add to the list nor mail again, stored lowercased
+CREATE TABLE suppressions(
+   email TEXT NOT NULL,
+   reason TEXT NOT NULL,
+   source TEXT NOT NULL,
+   created_at timestamptz NOT NULL,
+   PRIMARY KEY (email)
+);
+
+-- Carry over the subscribers who already left the list or were suppressed by a webhook
+INSERT INTO suppressions (email, reason, source, created_at)
+SELECT lower(email), 'unsubscribed', 'unsubscribe_link', now()
+FROM subscriptions
+WHERE status = 'unsubscribed'
+ON CONFLICT DO NOTHING;
+
+INSERT INTO suppressions (email, reason, source, created_at)
+SELECT
+    lower(s.email),
+    CASE
+        WHEN EXISTS (
+            SELECT 1 FROM delivery_events e
+            WHERE e.subscriber_id = s.id AND e.event_type = 'spam_complaint'
+        ) THEN 'spam_complaint'
+        ELSE 'hard_bounce'
+    END,
+    'postmark_webhook',
+    now()
+FROM subscriptions s
+WHERE s.status = 'suppressed'
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,16 @@
+-- Create Newsletter Images Table
+-- Images uploaded to be embedded in newsletter issues, sent along as inline attachments
+CREATE TABLE newsletter_images(
+   image_id uuid NOT NULL,
+   file_name TEXT NOT NULL,
+   content_type TEXT NOT NULL,
+   content BYTEA NOT NULL,
+   uploaded_at timestamptz NOT NULL,
+   PRIMARY KEY (image_id)
+);
+-- Images referenced by each issue
+CREATE TABLE newsletter_issue_images(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+   image_id uuid NOT NULL REFERENCES newsletter_images (image_id),
+   PRIMARY KEY (newsletter_issue_id, image_id)
+);
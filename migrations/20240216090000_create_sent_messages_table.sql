@@ -0,0 +1,18 @@
+-- Create Sent Messages Table
+-- One row per email the provider accepted. Its webhooks refer to emails by message id,
+-- which tells exactly which delivery they are about
+CREATE TABLE sent_messages(
+   id uuid NOT NULL,
+   message_id TEXT NULL UNIQUE,
+   subscriber_id uuid NULL REFERENCES subscriptions (id),
+   -- Null for the emails that aren't newsletter issues, e.g. confirmation emails
+   newsletter_issue_id uuid NULL REFERENCES newsletter_issues (newsletter_issue_id),
+   email TEXT NOT NULL,
+   error_code BIGINT NOT NULL,
+   submitted_at timestamptz NOT NULL,
+   PRIMARY KEY (id)
+);
+CREATE INDEX sent_messages_subscriber_id_idx ON sent_messages (subscriber_id);
+-- Bounces of a newsletter issue can be told apart from those of other emails
+ALTER TABLE delivery_events
+   ADD COLUMN newsletter_issue_id uuid NULL REFERENCES newsletter_issues (newsletter_issue_id);
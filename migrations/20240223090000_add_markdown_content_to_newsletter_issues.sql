@@ -0,0 +1,3 @@
+-- Add Markdown Content To Newsletter Issues
+-- Issues published with html and text content directly have no markdown
+ALTER TABLE newsletter_issues ADD COLUMN markdown_content TEXT NULL;
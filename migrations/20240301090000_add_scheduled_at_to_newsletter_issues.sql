@@ -0,0 +1,7 @@
+-- Add Scheduled At To Newsletter Issues
+-- Scheduled issues are stored right away but only published, and their deliveries queued,
+-- once their time comes
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_at timestamptz NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+CREATE INDEX newsletter_issues_due_idx ON newsletter_issues (scheduled_at)
+   WHERE published_at IS NULL;
@@ -0,0 +1,75 @@
+-- Add Review Workflow To Newsletter Issues
+-- Issues go draft -> in_review -> approved -> scheduled -> sending -> sent. Issues published
+-- before the workflow existed are left in the state they are in; unpublished ones need a review
+ALTER TABLE newsletter_issues
+   ADD COLUMN status TEXT NULL,
+   -- Null for the issues published before the workflow existed
+   ADD COLUMN author_id uuid NULL REFERENCES users (user_id),
+   ADD COLUMN approved_by uuid NULL REFERENCES users (user_id),
+   ADD CONSTRAINT newsletter_issues_approver_is_not_author CHECK (approved_by <> author_id);
+UPDATE newsletter_issues i SET status = CASE
+   WHEN published_at IS NULL AND scheduled_at IS NULL THEN 'draft'
+   WHEN published_at IS NULL THEN 'in_review'
+   WHEN EXISTS (
+      SELECT 1 FROM issue_delivery_queue q WHERE q.newsletter_issue_id = i.newsletter_issue_id
+   ) THEN 'sending'
+   ELSE 'sent'
+END;
+ALTER TABLE newsletter_issues
+   ALTER COLUMN status SET NOT NULL,
+   ALTER COLUMN status SET DEFAULT 'draft',
+   ADD CONSTRAINT newsletter_issues_status_check
+      CHECK (status IN ('draft', 'in_review', 'approved', 'scheduled', 'sending', 'sent'));
+DROP INDEX newsletter_issues_due_idx;
+CREATE INDEX newsletter_issues_due_idx ON newsletter_issues (scheduled_at)
+   WHERE status = 'scheduled';
+
+-- The only moves an issue can make, whoever updates it
+CREATE TABLE newsletter_issue_transitions(
+   from_status TEXT NOT NULL,
+   to_status TEXT NOT NULL,
+   PRIMARY KEY (from_status, to_status)
+);
+INSERT INTO newsletter_issue_transitions (from_status, to_status) VALUES
+   ('draft', 'in_review'),
+   -- Changes requested by the reviewer, or needed after the approval
+   ('in_review', 'draft'),
+   ('approved', 'draft'),
+   ('in_review', 'approved'),
+   ('approved', 'scheduled'),
+   -- Schedule cancelled
+   ('scheduled', 'approved'),
+   ('scheduled', 'sending'),
+   ('sending', 'sent');
+
+CREATE FUNCTION check_newsletter_issue_transition() RETURNS trigger AS $$
+BEGIN
+   IF NOT EXISTS (
+      SELECT 1 FROM newsletter_issue_transitions
+      WHERE from_status = OLD.status AND to_status = NEW.status
+   ) THEN
+      RAISE EXCEPTION 'A newsletter issue can''t go from % to %.', OLD.status, NEW.status
+         USING ERRCODE = 'NI001';
+   END IF;
+   RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER newsletter_issue_transition
+   BEFORE UPDATE OF status ON newsletter_issues
+   FOR EACH ROW
+   WHEN (OLD.status IS DISTINCT FROM NEW.status)
+   EXECUTE FUNCTION check_newsletter_issue_transition();
+
+-- Every version of an issue, the first one included
+CREATE TABLE newsletter_issue_revisions(
+   newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+   revision INT NOT NULL,
+   title TEXT NOT NULL,
+   markdown_content TEXT NULL,
+   text_content TEXT NOT NULL,
+   html_content TEXT NOT NULL,
+   edited_by uuid NOT NULL REFERENCES users (user_id),
+   edited_at timestamptz NOT NULL,
+   PRIMARY KEY (newsletter_issue_id, revision)
+);
@@ -0,0 +1,8 @@
+-- Lock the seeded admin
+-- Its password was published along with the code. Where it was never changed the account is
+-- locked with the hash of a secret nobody kept. Admins are created, and get back in, with
+-- `zero2prod create-admin <username>`
+UPDATE users
+SET password_hash = '$argon2id$v=19$m=15000,t=2,p=1$NV87ZV3F46CGfF+DBB40gQ$169lcWAo1p4ANGKGtMvqInjWGlGEJl41EDLrYnPnV4I'
+WHERE user_id = 'ddf8994f-d522-4659-8d02-c1d479057be6'
+   AND password_hash = '$argon2id$v=19$m=15000,t=2,p=1$HP1N8GoTaFoXho9bHr2Yog$q3L+2uSWaiRbn0iMPMkByCZ4TuYh4eStWq7H0Wqtqf0';
@@ -0,0 +1,46 @@
+-- Keep Editors From Approving Newsletter Issues
+-- The author isn't the only one to keep out: whoever edited the issue since its last approval,
+-- the edits made after a reopen included, can't approve the new version
+ALTER TABLE newsletter_issues
+   -- The last revision an approval was given for, kept when the approval is dropped
+   ADD COLUMN approved_revision INT NOT NULL DEFAULT 0;
+UPDATE newsletter_issues i SET approved_revision = COALESCE(
+   (SELECT MAX(revision) FROM newsletter_issue_revisions r
+   WHERE r.newsletter_issue_id = i.newsletter_issue_id),
+   0
+)
+WHERE approved_by IS NOT NULL;
+
+CREATE FUNCTION check_newsletter_issue_approver() RETURNS trigger AS $$
+BEGIN
+   -- Maintained here only, whatever the update says
+   NEW.approved_revision := OLD.approved_revision;
+   IF NEW.approved_by IS NULL OR NEW.approved_by IS NOT DISTINCT FROM OLD.approved_by THEN
+      RETURN NEW;
+   END IF;
+   IF EXISTS (
+      SELECT 1 FROM newsletter_issue_revisions
+      WHERE newsletter_issue_id = NEW.newsletter_issue_id
+         AND revision > OLD.approved_revision
+         AND edited_by = NEW.approved_by
+   ) THEN
+      RAISE EXCEPTION 'A newsletter issue can''t be approved by someone who edited it.'
+         USING ERRCODE = 'NI002';
+   END IF;
+   NEW.approved_revision := COALESCE(
+      (SELECT MAX(revision) FROM newsletter_issue_revisions
+      WHERE newsletter_issue_id = NEW.newsletter_issue_id),
+      0
+   );
+   RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER newsletter_issue_approver
+   BEFORE UPDATE ON newsletter_issues
+   FOR EACH ROW
+   WHEN (
+      OLD.approved_by IS DISTINCT FROM NEW.approved_by
+      OR OLD.approved_revision IS DISTINCT FROM NEW.approved_revision
+   )
+   EXECUTE FUNCTION check_newsletter_issue_approver();
@@ -0,0 +1,106 @@
+use std::fmt;
+use std::future::{ready, Future, Ready};
+use std::ops::Deref;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::ACCEPT;
+use actix_web::{HttpMessage, HttpResponse};
+use tracing_actix_web::RootSpan;
+use uuid::Uuid;
+
+use crate::session_state::TypedSession;
+use crate::utils::see_other;
+
+// Id of the user behind the session, available to the handlers as `web::ReqData<UserId>`
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// Lets through only the requests coming from a logged in user
+pub struct RejectAnonymousUsers;
+
+impl<S, B> Transform<S, ServiceRequest> for RejectAnonymousUsers
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RejectAnonymousUsersMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RejectAnonymousUsersMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RejectAnonymousUsersMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RejectAnonymousUsersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        // The inner service has to outlive the borrow of self, hence the Rc
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let session = req.extract::<TypedSession>().await?;
+            let response = match session.get_user_id() {
+                Ok(Some(user_id)) => {
+                    if let Some(root_span) = req.extensions().get::<RootSpan>() {
+                        root_span.record("user_id", tracing::field::display(&user_id));
+                    }
+                    req.extensions_mut().insert(UserId(user_id));
+                    return service
+                        .call(req)
+                        .await
+                        .map(ServiceResponse::map_into_left_body);
+                }
+                Ok(None) if is_api_client(&req) => HttpResponse::Unauthorized().finish(),
+                Ok(None) => see_other("/login"),
+                Err(e) => {
+                    tracing::error!("Failed to read the session: {:?}", e);
+                    HttpResponse::InternalServerError().finish()
+                }
+            };
+            Ok(req.into_response(response).map_into_right_body())
+        })
+    }
+}
+
+// Browsers ask for html (or anything at all), API clients for a specific non-html format
+fn is_api_client(req: &ServiceRequest) -> bool {
+    match req.headers().get(ACCEPT).and_then(|h| h.to_str().ok()) {
+        Some(accept) => !accept.contains("text/html") && !accept.contains("*/*"),
+        None => false,
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use secrecy::{ExposeSecret, Secret};
+use unicode_segmentation::UnicodeSegmentation;
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+// Passwords known to be leaked in data breaches, one per line
+const BREACHED_PASSWORDS: &str = include_str!("breached_passwords.txt");
+
+fn breached_passwords() -> &'static HashSet<&'static str> {
+    static PASSWORDS: OnceLock<HashSet<&'static str>> = OnceLock::new();
+    PASSWORDS.get_or_init(|| {
+        BREACHED_PASSWORDS
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect()
+    })
+}
+
+#[derive(Debug)]
+pub struct NewPassword(Secret<String>);
+
+impl NewPassword {
+    pub fn parse(s: Secret<String>) -> Result<NewPassword, String> {
+        let length = s.expose_secret().graphemes(true).count();
+        if length < MIN_PASSWORD_LENGTH {
+            return Err(format!(
+                "The new password must be at least {} characters long.",
+                MIN_PASSWORD_LENGTH
+            ));
+        }
+
+        if length > MAX_PASSWORD_LENGTH {
+            return Err(format!(
+                "The new password must be at most {} characters long.",
+                MAX_PASSWORD_LENGTH
+            ));
+        }
+
+        if breached_passwords().contains(s.expose_secret().as_str()) {
+            return Err(String::from(
+                "The new password has appeared in a data breach, please choose a different one.",
+            ));
+        }
+
+        Ok(Self(s))
+    }
+}
+
+impl ExposeSecret<String> for NewPassword {
+    fn expose_secret(&self) -> &String {
+        self.0.expose_secret()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NewPassword;
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    #[test]
+    fn test_11_characters_password_invalid() {
+        let password = Secret::new("a".repeat(11));
+        assert_err!(NewPassword::parse(password));
+    }
+
+    #[test]
+    fn test_12_characters_password_valid() {
+        let password = Secret::new("a̐".repeat(12));
+        assert_ok!(NewPassword::parse(password));
+    }
+
+    #[test]
+    fn test_128_characters_password_valid() {
+        let password = Secret::new("a̐".repeat(128));
+        assert_ok!(NewPassword::parse(password));
+    }
+
+    #[test]
+    fn test_129_characters_password_invalid() {
+        let password = Secret::new("a".repeat(129));
+        assert_err!(NewPassword::parse(password));
+    }
+
+    #[test]
+    fn test_breached_password_invalid() {
+        let password = Secret::new(String::from("password1234"));
+        assert_err!(NewPassword::parse(password));
+    }
+
+    #[test]
+    fn test_every_listed_password_invalid() {
+        for password in super::BREACHED_PASSWORDS.lines() {
+            assert_err!(NewPassword::parse(Secret::new(password.to_string())));
+        }
+    }
+}
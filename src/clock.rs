@@ -0,0 +1,14 @@
+use chrono::{DateTime, Utc};
+
+// Source of the current time, so that time dependent behaviour can be tested without waiting
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
@@ -1,8 +1,15 @@
+use std::num::NonZeroUsize;
+
+use actix_web::cookie::Key;
+use chrono_tz::Tz;
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{deserialize_bool_from_anything, deserialize_number_from_string};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 
 use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    EmailClient, FileSinkTransport, PostmarkTransport, RateLimiter, RetryPolicy, SmtpTransport,
+};
 
 pub enum Environment {
     Local,
@@ -42,13 +49,138 @@ pub struct Settings {
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailCLientSettings {
+    pub provider: EmailProvider,
+    // Postmark API address
     pub base_url: String,
     pub sender_email: String,
-    pub authorization_token: Secret<String>,
     pub timeout_miliseconds: u64,
+    pub retry: RetrySettings,
+    pub rate_limit: RateLimitSettings,
+    pub webhook: WebhookSettings,
+    // Only required by the matching provider. The token is Postmark's server token
+    pub authorization_token: Option<Secret<String>>,
+    pub smtp: Option<SmtpSettings>,
+    pub file_sink_directory: Option<String>,
+}
+
+// How emails leave the application
+#[derive(serde::Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailProvider {
+    Postmark,
+    Smtp,
+    // Writes the emails to disk instead of sending them, meant for development
+    File,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RetrySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: u32,
+    pub base_delay_miliseconds: u64,
+    pub max_delay_miliseconds: u64,
+    // Fraction of each delay that is randomised, between 0 and 1
+    pub jitter: f64,
+}
+
+impl RetrySettings {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: std::time::Duration::from_millis(self.base_delay_miliseconds),
+            max_delay: std::time::Duration::from_millis(self.max_delay_miliseconds),
+            jitter: self.jitter,
+        }
+    }
+}
+
+// Postmark authenticates its webhook calls with either of these, depending on how the
+// webhook is set up: basic auth credentials in the url or a custom header
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub username: String,
+    pub password: Secret<String>,
+    pub secret: Secret<String>,
+}
+
+// Outbound throttling, keeps us below the provider's own limits
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitSettings {
+    pub messages_per_second: f64,
+    // With no request allowed in flight nothing would ever be sent
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_concurrent_requests: NonZeroUsize,
+}
+
+impl RateLimitSettings {
+    pub fn rate_limiter(&self) -> Result<RateLimiter, String> {
+        if self.messages_per_second.is_nan() || self.messages_per_second <= 0.0 {
+            return Err(format!(
+                "The email rate limit must be a positive number of messages per second, got {}.",
+                self.messages_per_second
+            ));
+        }
+        Ok(RateLimiter::new(
+            self.messages_per_second,
+            self.max_concurrent_requests.get(),
+        ))
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: String,
+    pub password: Secret<String>,
+    #[serde(deserialize_with = "deserialize_bool_from_anything")]
+    pub starttls: bool,
 }
 
 impl EmailCLientSettings {
+    pub fn client(self) -> Result<EmailClient, String> {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        let retry_policy = self.retry.policy();
+        let rate_limiter = self.rate_limit.rate_limiter()?;
+        let client = match self.provider {
+            EmailProvider::Postmark => {
+                let base_url = reqwest::Url::parse(&self.base_url)
+                    .unwrap_or_else(|_| panic!("Can't parse {} as url", self.base_url));
+                let authorization_token = self
+                    .authorization_token
+                    .expect("The postmark provider requires an authorization_token.");
+                let transport = PostmarkTransport::new(base_url, authorization_token, timeout);
+                EmailClient::new(sender_email, transport, retry_policy, rate_limiter)
+            }
+            EmailProvider::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("The smtp provider requires the smtp settings.");
+                let transport = SmtpTransport::new(
+                    &smtp.host,
+                    smtp.port,
+                    smtp.username,
+                    smtp.password,
+                    smtp.starttls,
+                    timeout,
+                )
+                .expect("Unable to set up the SMTP transport.");
+                EmailClient::new(sender_email, transport, retry_policy, rate_limiter)
+            }
+            EmailProvider::File => {
+                let directory = self
+                    .file_sink_directory
+                    .expect("The file provider requires a file_sink_directory.");
+                let transport = FileSinkTransport::new(&directory)
+                    .unwrap_or_else(|_| panic!("Unable to create the {} directory", directory));
+                EmailClient::new(sender_email, transport, retry_policy, rate_limiter)
+            }
+        };
+        Ok(client)
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -68,6 +200,58 @@ pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
+    // Public address of the application, used to build links sent by email
+    pub base_url: String,
+    // Key used to sign the links we hand out, such as unsubscribe links
+    pub hmac_secret: Secret<String>,
+    // Signs and encrypts the session and flash message cookies, at least 64 bytes long
+    pub session_secret: Secret<String>,
+    pub session_store: SessionStoreType,
+    // Where the email templates are read from at startup
+    pub templates_directory: String,
+    // The only addresses test sends of a newsletter issue go to
+    pub staff_emails: Vec<String>,
+    // Schedule times without an offset are wall clock times of this timezone
+    pub timezone: Tz,
+}
+
+// Cookie keys are built from at least this many bytes
+const MIN_SESSION_SECRET_LENGTH: usize = 64;
+
+impl ApplicationSettings {
+    // A leaked link signing key must not let anyone forge sessions, so the two secrets have
+    // to differ
+    pub fn session_key(&self) -> Result<Key, String> {
+        let secret = self.session_secret.expose_secret();
+        if secret.len() < MIN_SESSION_SECRET_LENGTH {
+            return Err(format!(
+                "The session secret must be at least {} bytes long.",
+                MIN_SESSION_SECRET_LENGTH
+            ));
+        }
+        if secret == self.hmac_secret.expose_secret() {
+            return Err(String::from(
+                "The session secret must be different from the hmac secret.",
+            ));
+        }
+        Ok(Key::from(secret.as_bytes()))
+    }
+
+    pub fn staff_emails(&self) -> Result<Vec<SubscriberEmail>, String> {
+        self.staff_emails
+            .iter()
+            .map(|email| SubscriberEmail::parse(email.clone()))
+            .collect()
+    }
+}
+
+// Where the admin sessions are kept
+#[derive(serde::Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStoreType {
+    Postgres,
+    // Sessions are lost on restart, only meant for tests
+    InMemory,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -134,3 +318,63 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
 
     settings.try_deserialize::<Settings>()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use claims::assert_err;
+    use secrecy::Secret;
+
+    use super::{ApplicationSettings, RateLimitSettings, SessionStoreType};
+
+    fn settings(hmac_secret: &str, session_secret: &str) -> ApplicationSettings {
+        ApplicationSettings {
+            port: 8000,
+            host: String::from("127.0.0.1"),
+            base_url: String::from("http://127.0.0.1"),
+            hmac_secret: Secret::new(hmac_secret.to_owned()),
+            session_secret: Secret::new(session_secret.to_owned()),
+            session_store: SessionStoreType::InMemory,
+            templates_directory: String::from("templates"),
+            staff_emails: vec![],
+            timezone: chrono_tz::UTC,
+        }
+    }
+
+    #[test]
+    fn session_secrets_of_64_bytes_are_accepted() {
+        assert!(settings("a", &"s".repeat(64)).session_key().is_ok());
+    }
+
+    #[test]
+    fn short_session_secrets_are_rejected() {
+        assert_err!(settings("a", &"s".repeat(63)).session_key().map(|_| ()));
+    }
+
+    #[test]
+    fn the_session_secret_must_differ_from_the_hmac_secret() {
+        let secret = "s".repeat(64);
+        assert_err!(settings(&secret, &secret).session_key().map(|_| ()));
+    }
+
+    #[test]
+    fn rates_that_are_not_positive_are_rejected() {
+        for messages_per_second in [0.0, -1.0, f64::NAN] {
+            let rate_limit = RateLimitSettings {
+                messages_per_second,
+                max_concurrent_requests: NonZeroUsize::new(1).unwrap(),
+            };
+            assert_err!(rate_limit.rate_limiter().map(|_| ()));
+        }
+    }
+
+    #[test]
+    fn a_concurrency_limit_of_zero_is_rejected() {
+        let rate_limit = serde_json::json!({
+            "messages_per_second": 10.0,
+            "max_concurrent_requests": "0",
+        });
+        assert_err!(serde_json::from_value::<RateLimitSettings>(rate_limit).map(|_| ()));
+    }
+}
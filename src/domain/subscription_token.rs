@@ -0,0 +1,78 @@
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+
+const SUBSCRIPTION_TOKEN_LENGTH: usize = 25;
+
+#[derive(Debug)]
+pub struct SubscriptionToken(String);
+
+impl SubscriptionToken {
+    // Random 25 characters long alphanumeric token, case sensitive
+    pub fn generate() -> SubscriptionToken {
+        let mut rng = thread_rng();
+        let token = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(SUBSCRIPTION_TOKEN_LENGTH)
+            .collect();
+        Self(token)
+    }
+
+    pub fn parse(s: String) -> Result<SubscriptionToken, String> {
+        if s.len() != SUBSCRIPTION_TOKEN_LENGTH {
+            return Err(format!(
+                "Subscription token has to be {} characters long",
+                SUBSCRIPTION_TOKEN_LENGTH
+            ));
+        }
+
+        if !s.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(String::from(
+                "Subscription token can only contain alphanumeric characters",
+            ));
+        }
+
+        Ok(Self(s))
+    }
+}
+
+impl AsRef<str> for SubscriptionToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriptionToken;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn test_generated_token_valid() {
+        let token = SubscriptionToken::generate();
+        assert_ok!(SubscriptionToken::parse(token.as_ref().to_string()));
+    }
+
+    #[test]
+    fn test_empty_string_invalid() {
+        let token = String::from("");
+        assert_err!(SubscriptionToken::parse(token));
+    }
+
+    #[test]
+    fn test_too_short_token_invalid() {
+        let token = "a".repeat(24);
+        assert_err!(SubscriptionToken::parse(token));
+    }
+
+    #[test]
+    fn test_too_long_token_invalid() {
+        let token = "a".repeat(26);
+        assert_err!(SubscriptionToken::parse(token));
+    }
+
+    #[test]
+    fn test_token_with_non_alphanumeric_characters_invalid() {
+        let token = format!("{}-", "a".repeat(24));
+        assert_err!(SubscriptionToken::parse(token));
+    }
+}
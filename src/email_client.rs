@@ -1,73 +1,492 @@
-use reqwest::Client;
-use secrecy::{ExposeSecret, Secret};
+mod attachment;
+mod file_sink;
+mod postmark;
+mod rate_limit;
+mod retry;
+mod smtp;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use actix_web::web::Data;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use lettre::message::header::ContentType;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart, SinglePart};
+use lettre::Message;
 
 use crate::domain::SubscriberEmail;
 
+pub use attachment::{message_size, Attachment, MAX_MESSAGE_SIZE};
+pub use file_sink::FileSinkTransport;
+pub use postmark::PostmarkTransport;
+pub use rate_limit::{RateLimiter, RateLimiterMetrics};
+pub use retry::RetryPolicy;
+pub use smtp::SmtpTransport;
+
+use attachment::check_message_size;
+
+// Provider agnostic view of an email, ready to be handed over to a transport
+pub struct Email<'a> {
+    pub from: &'a str,
+    pub to: &'a str,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+    pub headers: Vec<EmailHeader<'a>>,
+    pub options: &'a EmailOptions<'a>,
+}
+
+#[derive(Clone)]
+pub struct EmailHeader<'a> {
+    pub name: &'a str,
+    pub value: String,
+}
+
+// Everything an email can carry besides its content
+#[derive(Clone, Default)]
+pub struct EmailOptions<'a> {
+    pub message_stream: MessageStream,
+    pub reply_to: Option<&'a str>,
+    pub cc: Vec<&'a str>,
+    pub bcc: Vec<&'a str>,
+    pub headers: Vec<EmailHeader<'a>>,
+    // Postmark groups its statistics by tag
+    pub tag: Option<&'a str>,
+    // Handed back by Postmark's webhooks, to tell which email they are about
+    pub metadata: BTreeMap<&'a str, String>,
+    pub attachments: &'a [Attachment],
+}
+
+// Postmark keeps bulk emails apart from transactional ones, so that the reputation of the
+// latter doesn't suffer from the former. Sending bulk emails on a transactional stream
+// breaks their terms of service
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MessageStream {
+    #[default]
+    Transactional,
+    Broadcast,
+}
+
+impl MessageStream {
+    // Ids of the streams every Postmark server comes with
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Transactional => "outbound",
+            Self::Broadcast => "broadcast",
+        }
+    }
+}
+
+// Delivers emails on behalf of the EmailClient, one implementation per provider
+#[async_trait::async_trait]
+pub trait EmailTransport: Send + Sync {
+    async fn send(&self, email: &Email<'_>) -> Result<SentMessage, EmailError>;
+
+    // One result per email, in the same order. Providers without a batch API get the
+    // emails one at a time
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<SentMessage, EmailError>> {
+        let mut results = Vec::with_capacity(emails.len());
+        for email in emails {
+            results.push(self.send(email).await);
+        }
+        results
+    }
+}
+
+// What the provider told us about an email it accepted
+#[derive(Clone, Debug, PartialEq)]
+pub struct SentMessage {
+    // The provider's id for the email, which its webhooks refer to. None when the provider
+    // didn't give one back
+    pub message_id: Option<String>,
+    pub submitted_at: DateTime<Utc>,
+    // Postmark's ErrorCode, always 0 for accepted emails
+    pub error_code: i64,
+}
+
+impl SentMessage {
+    // For the transports handing over RFC 5322 messages, whose id we set ourselves
+    fn from_message(message: &Message) -> Self {
+        Self {
+            message_id: message
+                .headers()
+                .get_raw("Message-ID")
+                .map(|id| id.to_string()),
+            submitted_at: Utc::now(),
+            error_code: 0,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum EmailError {
+    #[error("Timed out waiting for the email provider.")]
+    Timeout,
+    #[error("The email provider rejected our credentials.")]
+    Unauthorized,
+    #[error("The recipient can't receive emails: {0}")]
+    InvalidRecipient(String),
+    // The provider asks us to slow down, and possibly how long to wait for
+    #[error("Throttled by the email provider.")]
+    RateLimited { retry_after: Option<Duration> },
+    // The provider refused the email for any other reason. The code is the provider's
+    // own, e.g. Postmark's ErrorCode or an SMTP reply code
+    #[error("The email provider refused the email with code {code}: {message}")]
+    ProviderError { code: i64, message: String },
+    // The email never made it to the provider: connection failures, provider outages, I/O
+    #[error("Failed to hand the email over to the provider: {0}")]
+    Transport(String),
+    #[error("The email is {size} bytes, more than the {MAX_MESSAGE_SIZE} bytes providers accept.")]
+    MessageTooLarge { size: usize },
+}
+
+impl EmailError {
+    // Sending this very email again will fail the same way
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidRecipient(_) | Self::ProviderError { .. } | Self::MessageTooLarge { .. }
+        )
+    }
+
+    pub(crate) fn transport(e: anyhow::Error) -> Self {
+        Self::Transport(format!("{:#}", e))
+    }
+}
+
+pub struct BatchEmail<'a> {
+    pub recipient: SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub unsubscribe_link: Option<String>,
+    pub options: EmailOptions<'a>,
+}
+
+// Tells the caller which recipients still need to be retried
+#[derive(Default)]
+pub struct BatchReport {
+    pub succeeded: Vec<SucceededEmail>,
+    pub failed: Vec<FailedEmail>,
+}
+
+pub struct SucceededEmail {
+    pub recipient: SubscriberEmail,
+    pub message: SentMessage,
+}
+
+pub struct FailedEmail {
+    pub recipient: SubscriberEmail,
+    pub error: EmailError,
+}
+
 pub struct EmailClient {
-    http_client: Client,
-    base_url: reqwest::Url,
     sender: SubscriberEmail,
-    authorization_token: Secret<String>,
+    transport: Box<dyn EmailTransport>,
+    retry_policy: RetryPolicy,
+    // Shared by every actix worker, they all hold the same Data<EmailClient>
+    rate_limiter: RateLimiter,
 }
 
 impl EmailClient {
     pub fn new(
-        base_url: reqwest::Url,
         sender: SubscriberEmail,
-        authorization_token: Secret<String>,
-        timeout: std::time::Duration,
+        transport: impl EmailTransport + 'static,
+        retry_policy: RetryPolicy,
+        rate_limiter: RateLimiter,
     ) -> Self {
         Self {
-            http_client: Client::builder()
-                .timeout(timeout)
-                .build()
-                .expect("Unable to build the HTTP client."),
-            base_url,
             sender,
-            authorization_token,
+            transport: Box::new(transport),
+            retry_policy,
+            rate_limiter,
         }
     }
 
+    pub fn rate_limiter_metrics(&self) -> RateLimiterMetrics {
+        self.rate_limiter.metrics()
+    }
+
     pub async fn send_email(
         &self,
         recipient: SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
-        let url =
-            reqwest::Url::join(&self.base_url, "/email").expect("Unable to create /email url.");
-        let request_body = SendEmailRequest {
+        unsubscribe_link: Option<&str>,
+    ) -> Result<SentMessage, EmailError> {
+        self.send_email_with_options(
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_link,
+            &EmailOptions::default(),
+        )
+        .await
+    }
+
+    pub async fn send_email_with_options(
+        &self,
+        recipient: SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_link: Option<&str>,
+        options: &EmailOptions<'_>,
+    ) -> Result<SentMessage, EmailError> {
+        // Refused by the provider anyway, no need to spend a request on it
+        check_message_size(html_content, text_content, options.attachments)?;
+        let email = self.email(
+            &recipient,
+            subject,
+            html_content,
+            text_content,
+            unsubscribe_link,
+            options,
+        );
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let permit = self.rate_limiter.acquire(1).await;
+            let outcome = self.transport.send(&email).await;
+            drop(permit);
+            let e = match outcome {
+                Ok(message) => return Ok(message),
+                Err(e) => e,
+            };
+            // Only worth retrying right away when the provider had a hiccup
+            let delay = match &e {
+                EmailError::Timeout | EmailError::Transport(_) => {
+                    self.retry_policy.delay_after(attempts, None)
+                }
+                EmailError::RateLimited { retry_after } => {
+                    self.retry_policy.delay_after(attempts, *retry_after)
+                }
+                _ => None,
+            };
+            match delay {
+                Some(delay) => {
+                    tracing::warn!(
+                        "Failed to send an email (attempt {}), retrying in {:?}: {:?}",
+                        attempts,
+                        delay,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                None => return Err(e),
+            }
+        }
+    }
+
+    pub async fn send_batch(&self, messages: Vec<BatchEmail<'_>>) -> BatchReport {
+        let mut report = BatchReport::default();
+        let mut sendable = Vec::with_capacity(messages.len());
+        for message in messages {
+            match check_message_size(
+                message.html_content,
+                message.text_content,
+                message.options.attachments,
+            ) {
+                Ok(()) => sendable.push(message),
+                Err(error) => report.failed.push(FailedEmail {
+                    recipient: message.recipient,
+                    error,
+                }),
+            }
+        }
+        let messages = sendable;
+
+        let results = {
+            let emails: Vec<Email> = messages
+                .iter()
+                .map(|message| {
+                    self.email(
+                        &message.recipient,
+                        message.subject,
+                        message.html_content,
+                        message.text_content,
+                        message.unsubscribe_link.as_deref(),
+                        &message.options,
+                    )
+                })
+                .collect();
+            let _permit = self.rate_limiter.acquire(emails.len()).await;
+            self.transport.send_batch(&emails).await
+        };
+
+        for (message, result) in messages.into_iter().zip(results) {
+            match result {
+                Ok(sent) => report.succeeded.push(SucceededEmail {
+                    recipient: message.recipient,
+                    message: sent,
+                }),
+                Err(error) => report.failed.push(FailedEmail {
+                    recipient: message.recipient,
+                    error,
+                }),
+            }
+        }
+        report
+    }
+
+    fn email<'a>(
+        &'a self,
+        recipient: &'a SubscriberEmail,
+        subject: &'a str,
+        html_content: &'a str,
+        text_content: &'a str,
+        unsubscribe_link: Option<&str>,
+        options: &'a EmailOptions<'a>,
+    ) -> Email<'a> {
+        let mut headers = match unsubscribe_link {
+            // RFC 8058 one-click unsubscribe, required by Gmail and Yahoo for bulk senders
+            Some(link) => vec![
+                EmailHeader {
+                    name: "List-Unsubscribe",
+                    value: format!("<{}>", link),
+                },
+                EmailHeader {
+                    name: "List-Unsubscribe-Post",
+                    value: String::from("List-Unsubscribe=One-Click"),
+                },
+            ],
+            None => Vec::new(),
+        };
+        headers.extend(options.headers.iter().cloned());
+        Email {
             from: self.sender.as_ref(),
             to: recipient.as_ref(),
             subject,
             html_body: html_content,
             text_body: text_content,
-        };
-        let _builder = self
-            .http_client
-            .post(url)
-            .header(
-                "X-Postmark-Server-Token",
-                self.authorization_token.expose_secret(),
-            )
-            .json(&request_body)
-            .send()
-            .await?
-            .error_for_status()?;
+            headers,
+            options,
+        }
+    }
+}
 
-        Ok(())
+// Logs how long sends waited on the rate limiter, once a minute. Quiet minutes are skipped
+pub async fn report_rate_limiter_metrics_until_stopped(
+    email_client: Data<EmailClient>,
+) -> Result<(), std::io::Error> {
+    let mut reported_permits = 0;
+    loop {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let metrics = email_client.rate_limiter_metrics();
+        if metrics.permits == reported_permits {
+            continue;
+        }
+        reported_permits = metrics.permits;
+        tracing::info!(
+            permits = metrics.permits,
+            total_wait_ms = metrics.total_wait.as_millis() as u64,
+            average_wait_ms = metrics.average_wait().as_millis() as u64,
+            max_wait_ms = metrics.max_wait.as_millis() as u64,
+            "Email rate limiter waits since startup"
+        );
     }
 }
 
-#[derive(serde::Serialize)]
-#[serde(rename_all = "PascalCase")]
-struct SendEmailRequest<'a> {
-    from: &'a str,
-    to: &'a str,
-    subject: &'a str,
-    html_body: &'a str,
-    text_body: &'a str,
+// RFC 5322 message, shared by the transports speaking plain email rather than an HTTP API
+fn build_message(email: &Email<'_>) -> Result<Message, EmailError> {
+    let to = email
+        .to
+        .parse::<Mailbox>()
+        .map_err(|e| EmailError::InvalidRecipient(e.to_string()))?;
+    let mut builder = Message::builder()
+        .from(
+            email
+                .from
+                .parse::<Mailbox>()
+                .context("Invalid sender address.")
+                .map_err(EmailError::transport)?,
+        )
+        .to(to)
+        .subject(email.subject);
+    let options = email.options;
+    if let Some(reply_to) = options.reply_to {
+        builder = builder.reply_to(
+            reply_to
+                .parse::<Mailbox>()
+                .context("Invalid reply-to address.")
+                .map_err(EmailError::transport)?,
+        );
+    }
+    for cc in &options.cc {
+        builder = builder.cc(cc
+            .parse::<Mailbox>()
+            .map_err(|e| EmailError::InvalidRecipient(e.to_string()))?);
+    }
+    for bcc in &options.bcc {
+        builder = builder.bcc(
+            bcc.parse::<Mailbox>()
+                .map_err(|e| EmailError::InvalidRecipient(e.to_string()))?,
+        );
+    }
+    // Postmark reads the tag, metadata and stream from these headers when relaying through
+    // its SMTP server. Other servers ignore them
+    let mut headers: Vec<(String, String)> = email
+        .headers
+        .iter()
+        .map(|header| (header.name.to_string(), header.value.clone()))
+        .collect();
+    if let Some(tag) = options.tag {
+        headers.push((String::from("X-PM-Tag"), tag.to_string()));
+    }
+    for (key, value) in &options.metadata {
+        headers.push((format!("X-PM-Metadata-{}", key), value.clone()));
+    }
+    headers.push((
+        String::from("X-PM-Message-Stream"),
+        options.message_stream.as_str().to_string(),
+    ));
+    for (name, value) in headers {
+        let name = HeaderName::new_from_ascii(name)
+            .context("Invalid email header name.")
+            .map_err(EmailError::transport)?;
+        builder = builder.raw_header(HeaderValue::new(name, value));
+    }
+    let mut body =
+        MultiPart::alternative_plain_html(email.text_body.to_string(), email.html_body.to_string());
+    // Inline images go along with the html in a multipart/related, the rest of the
+    // attachments wrap everything in a multipart/mixed
+    let (inline, attached): (Vec<_>, Vec<_>) = options
+        .attachments
+        .iter()
+        .partition(|a| a.content_id.is_some());
+    if !inline.is_empty() {
+        let mut related = MultiPart::related().multipart(body);
+        for attachment in inline {
+            related = related.singlepart(attachment_part(attachment)?);
+        }
+        body = related;
+    }
+    if !attached.is_empty() {
+        let mut mixed = MultiPart::mixed().multipart(body);
+        for attachment in attached {
+            mixed = mixed.singlepart(attachment_part(attachment)?);
+        }
+        body = mixed;
+    }
+    builder
+        .multipart(body)
+        .context("Failed to build the email message.")
+        .map_err(EmailError::transport)
+}
+
+fn attachment_part(attachment: &Attachment) -> Result<SinglePart, EmailError> {
+    let content_type = ContentType::parse(&attachment.content_type)
+        .with_context(|| format!("Invalid content type for {}.", attachment.name))
+        .map_err(EmailError::transport)?;
+    let builder = match &attachment.content_id {
+        Some(content_id) => lettre::message::Attachment::new_inline(content_id.clone()),
+        None => lettre::message::Attachment::new(attachment.name.clone()),
+    };
+    Ok(builder.body(attachment.content.clone(), content_type))
 }
 
 #[cfg(test)]
@@ -84,11 +503,15 @@ mod tests {
     use secrecy::Secret;
     use wiremock::{
         matchers::{any, header, header_exists, method, path},
-        Mock, MockServer, Request, ResponseTemplate,
+        Mock, MockServer, Request, Respond, ResponseTemplate,
     };
 
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{
+        Attachment, BatchEmail, EmailClient, EmailError, EmailHeader, EmailOptions,
+        FileSinkTransport, MessageStream, PostmarkTransport, RateLimiter, RetryPolicy, SentMessage,
+        MAX_MESSAGE_SIZE,
+    };
 
     struct SendEmailBodyMatcher;
 
@@ -107,6 +530,76 @@ mod tests {
         }
     }
 
+    struct UnsubscribeHeadersMatcher(String);
+
+    impl wiremock::Match for UnsubscribeHeadersMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                let expected = serde_json::json!([
+                    {"Name": "List-Unsubscribe", "Value": format!("<{}>", self.0)},
+                    {"Name": "List-Unsubscribe-Post", "Value": "List-Unsubscribe=One-Click"},
+                ]);
+                body.get("Headers") == Some(&expected)
+            } else {
+                false
+            }
+        }
+    }
+
+    // Answers like Postmark's batch endpoint, rejecting the given recipients as inactive
+    struct BatchResponder(Vec<String>);
+
+    impl Respond for BatchResponder {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<serde_json::Value> = messages
+                .iter()
+                .map(|message| {
+                    let to = message["To"].as_str().unwrap();
+                    if self.0.iter().any(|failing| failing == to) {
+                        serde_json::json!({
+                            "ErrorCode": 406,
+                            "Message": "You tried to send to a recipient that has been marked as inactive.",
+                            "To": to
+                        })
+                    } else {
+                        serde_json::json!({
+                            "ErrorCode": 0,
+                            "Message": "OK",
+                            "To": to,
+                            "MessageID": format!("message-{}", to),
+                            "SubmittedAt": "2024-02-16T10:00:00.1234567-05:00"
+                        })
+                    }
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        }
+    }
+
+    fn set_batch(size: usize) -> Vec<BatchEmail<'static>> {
+        (0..size)
+            .map(|i| BatchEmail {
+                recipient: SubscriberEmail::parse(format!("subscriber-{}@example.com", i)).unwrap(),
+                subject: "Newsletter",
+                html_content: "<p>Newsletter</p>",
+                text_content: "Newsletter",
+                unsubscribe_link: Some(format!("https://my-api.com/unsubscribe?id={}", i)),
+                options: EmailOptions::default(),
+            })
+            .collect()
+    }
+
+    fn set_inline_image() -> Attachment {
+        Attachment {
+            name: String::from("cover.png"),
+            content_type: String::from("image/png"),
+            content: b"not really a png".to_vec(),
+            content_id: Some(String::from("cover")),
+        }
+    }
+
     fn set_subject() -> String {
         Sentence(1..2).fake()
     }
@@ -119,16 +612,52 @@ mod tests {
         SubscriberEmail::parse(SafeEmail().fake()).expect("Unable to generate email from SafeEmail")
     }
 
+    // Generous enough not to get in the way
+    fn set_rate_limiter() -> RateLimiter {
+        RateLimiter::new(1000.0, 10)
+    }
+
     fn set_email_client(base_url: Url) -> EmailClient {
-        let sender = set_email();
-        EmailClient::new(
+        let transport = PostmarkTransport::new(
             base_url,
-            sender,
             Secret::new(Faker.fake()),
             std::time::Duration::from_millis(200),
+        );
+        EmailClient::new(
+            set_email(),
+            transport,
+            RetryPolicy::no_retries(),
+            set_rate_limiter(),
         )
     }
 
+    fn set_retrying_email_client(base_url: Url) -> EmailClient {
+        let transport = PostmarkTransport::new(
+            base_url,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        );
+        let retry_policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: std::time::Duration::from_millis(10),
+            max_delay: std::time::Duration::from_secs(2),
+            jitter: 0.0,
+        };
+        EmailClient::new(set_email(), transport, retry_policy, set_rate_limiter())
+    }
+
+    async fn send_any_email(email_client: &EmailClient) -> Result<SentMessage, EmailError> {
+        email_client
+            .send_email(
+                set_email(),
+                &set_subject(),
+                &set_content(),
+                &set_content(),
+                None,
+            )
+            .await
+    }
+
     #[tokio::test]
     async fn test_send_email_sends_expected_request() {
         // Arrange HTTP background server on random local port
@@ -150,13 +679,49 @@ mod tests {
 
         // Send the actual email to the mock server
         let server_response = email_client
-            .send_email(set_email(), &set_subject(), &set_content(), &set_content())
+            .send_email(
+                set_email(),
+                &set_subject(),
+                &set_content(),
+                &set_content(),
+                None,
+            )
             .await;
 
         // Assert
         assert_ok!(server_response);
     }
 
+    #[tokio::test]
+    async fn test_send_email_with_unsubscribe_link_sends_list_unsubscribe_headers() {
+        // Arrange HTTP background server on random local port
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri())
+            .unwrap_or_else(|_| panic!("Can't parse {} as url", mock_server.uri()));
+        let email_client = set_email_client(url);
+        let unsubscribe_link = "https://my-api.com/subscriptions/unsubscribe?token=abc";
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(UnsubscribeHeadersMatcher(unsubscribe_link.to_string()))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let server_response = email_client
+            .send_email(
+                set_email(),
+                &set_subject(),
+                &set_content(),
+                &set_content(),
+                Some(unsubscribe_link),
+            )
+            .await;
+
+        assert_ok!(server_response);
+    }
+
     #[tokio::test]
     async fn test_send_email_fails_if_server_retuns_500() {
         // Arrange HTTP background server on random local port
@@ -173,7 +738,13 @@ mod tests {
             .await;
 
         let server_response = email_client
-            .send_email(set_email(), &set_subject(), &set_content(), &set_content())
+            .send_email(
+                set_email(),
+                &set_subject(),
+                &set_content(),
+                &set_content(),
+                None,
+            )
             .await;
 
         assert_err!(server_response);
@@ -196,9 +767,681 @@ mod tests {
             .await;
 
         let server_response = email_client
-            .send_email(set_email(), &set_subject(), &set_content(), &set_content())
+            .send_email(
+                set_email(),
+                &set_subject(),
+                &set_content(),
+                &set_content(),
+                None,
+            )
             .await;
 
         assert_err!(server_response);
     }
+
+    #[tokio::test]
+    async fn test_send_email_with_attachments_sends_them_base64_encoded() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_email_client(url);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email_with_options(
+                set_email(),
+                &set_subject(),
+                r#"<img src="cid:cover">"#,
+                &set_content(),
+                None,
+                &EmailOptions {
+                    attachments: &[set_inline_image()],
+                    ..Default::default()
+                },
+            )
+            .await;
+
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(
+            body["Attachments"],
+            serde_json::json!([{
+                "Name": "cover.png",
+                "Content": "bm90IHJlYWxseSBhIHBuZw==",
+                "ContentType": "image/png",
+                "ContentID": "cid:cover",
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_email_returns_the_message_id_postmark_assigned() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_email_client(url);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "To": "receiver@example.com",
+                "SubmittedAt": "2014-02-17T07:25:01.4178645-05:00",
+                "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+                "ErrorCode": 0,
+                "Message": "OK"
+            })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let sent = send_any_email(&email_client).await.unwrap();
+
+        assert_eq!(
+            sent,
+            SentMessage {
+                message_id: Some(String::from("0a129aee-e1cd-480d-b08d-4f48548ff48d")),
+                submitted_at: "2014-02-17T12:25:01.4178645Z".parse().unwrap(),
+                error_code: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_email_succeeds_even_if_the_response_is_unreadable() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_email_client(url);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html>OK</html>"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let sent = send_any_email(&email_client).await.unwrap();
+
+        assert_eq!(sent.message_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_send_email_with_options_sends_them_to_postmark() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_email_client(url);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let options = EmailOptions {
+            message_stream: MessageStream::Broadcast,
+            reply_to: Some("editor@example.com"),
+            cc: vec!["cc-1@example.com", "cc-2@example.com"],
+            bcc: vec!["archive@example.com"],
+            headers: vec![EmailHeader {
+                name: "X-Campaign",
+                value: String::from("spring"),
+            }],
+            tag: Some("newsletter"),
+            metadata: [("issue", String::from("42"))].into(),
+            attachments: &[],
+        };
+        let outcome = email_client
+            .send_email_with_options(
+                set_email(),
+                &set_subject(),
+                &set_content(),
+                &set_content(),
+                None,
+                &options,
+            )
+            .await;
+
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["MessageStream"], "broadcast");
+        assert_eq!(body["ReplyTo"], "editor@example.com");
+        assert_eq!(body["Cc"], "cc-1@example.com, cc-2@example.com");
+        assert_eq!(body["Bcc"], "archive@example.com");
+        assert_eq!(
+            body["Headers"],
+            serde_json::json!([{"Name": "X-Campaign", "Value": "spring"}])
+        );
+        assert_eq!(body["Tag"], "newsletter");
+        assert_eq!(body["Metadata"], serde_json::json!({"issue": "42"}));
+    }
+
+    #[tokio::test]
+    async fn test_send_email_goes_out_on_the_transactional_stream_by_default() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_email_client(url);
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(send_any_email(&email_client).await);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["MessageStream"], "outbound");
+        assert!(body.get("Tag").is_none());
+        assert!(body.get("Metadata").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_writes_the_options_as_headers() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let transport =
+            FileSinkTransport::new(&directory).expect("Failed to create the sink directory");
+        let email_client = EmailClient::new(
+            set_email(),
+            transport,
+            RetryPolicy::no_retries(),
+            set_rate_limiter(),
+        );
+
+        let options = EmailOptions {
+            message_stream: MessageStream::Broadcast,
+            reply_to: Some("editor@example.com"),
+            cc: vec!["cc@example.com"],
+            headers: vec![EmailHeader {
+                name: "X-Campaign",
+                value: String::from("spring"),
+            }],
+            tag: Some("newsletter"),
+            metadata: [("issue", String::from("42"))].into(),
+            ..Default::default()
+        };
+        let outcome = email_client
+            .send_email_with_options(
+                set_email(),
+                &set_subject(),
+                &set_content(),
+                &set_content(),
+                None,
+                &options,
+            )
+            .await;
+
+        assert_ok!(outcome);
+        let file = std::fs::read_dir(&directory)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let message = std::fs::read_to_string(file).unwrap();
+        for header in [
+            "Reply-To: editor@example.com",
+            "Cc: cc@example.com",
+            "X-Campaign: spring",
+            "X-PM-Tag: newsletter",
+            "X-PM-Metadata-issue: 42",
+            "X-PM-Message-Stream: broadcast",
+        ] {
+            assert!(message.contains(header), "{} is missing", header);
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_email_refuses_emails_over_the_size_limit() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_email_client(url);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let attachment = Attachment {
+            content: vec![0; MAX_MESSAGE_SIZE],
+            ..set_inline_image()
+        };
+        let outcome = email_client
+            .send_email_with_options(
+                set_email(),
+                &set_subject(),
+                &set_content(),
+                &set_content(),
+                None,
+                &EmailOptions {
+                    attachments: &[attachment],
+                    ..Default::default()
+                },
+            )
+            .await;
+
+        let error = outcome.unwrap_err();
+        assert!(matches!(error, EmailError::MessageTooLarge { .. }));
+        assert!(error.is_permanent());
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_embeds_inline_images_next_to_the_html() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let transport =
+            FileSinkTransport::new(&directory).expect("Failed to create the sink directory");
+        let email_client = EmailClient::new(
+            set_email(),
+            transport,
+            RetryPolicy::no_retries(),
+            set_rate_limiter(),
+        );
+
+        let outcome = email_client
+            .send_email_with_options(
+                set_email(),
+                &set_subject(),
+                r#"<img src="cid:cover">"#,
+                &set_content(),
+                None,
+                &EmailOptions {
+                    attachments: &[set_inline_image()],
+                    ..Default::default()
+                },
+            )
+            .await;
+
+        assert_ok!(outcome);
+        let file = std::fs::read_dir(&directory)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let message = std::fs::read_to_string(file).unwrap();
+        assert!(message.contains("multipart/related"));
+        assert!(message.contains("Content-ID: <cover>"));
+        assert!(message.contains("Content-Disposition: inline"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_writes_every_email_as_an_eml_file() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let transport =
+            FileSinkTransport::new(&directory).expect("Failed to create the sink directory");
+        let email_client = EmailClient::new(
+            set_email(),
+            transport,
+            RetryPolicy::no_retries(),
+            set_rate_limiter(),
+        );
+        let subject = set_subject();
+        let unsubscribe_link = "https://my-api.com/subscriptions/unsubscribe?token=abc";
+
+        let outcome = email_client
+            .send_email(
+                set_email(),
+                &subject,
+                &set_content(),
+                &set_content(),
+                Some(unsubscribe_link),
+            )
+            .await;
+
+        assert_ok!(outcome);
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let message = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(message.contains(&format!("Subject: {}", subject)));
+        assert!(message.contains(&format!("List-Unsubscribe: <{}>", unsubscribe_link)));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_splits_messages_in_chunks_of_500() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_email_client(url);
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(BatchResponder(Vec::new()))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        let report = email_client.send_batch(set_batch(1001)).await;
+
+        assert_eq!(report.succeeded.len(), 1001);
+        assert!(report.failed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_reports_the_recipients_postmark_rejected() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_email_client(url);
+
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(BatchResponder(vec![String::from(
+                "subscriber-1@example.com",
+            )]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let report = email_client.send_batch(set_batch(3)).await;
+
+        let succeeded: Vec<&str> = report
+            .succeeded
+            .iter()
+            .map(|r| r.recipient.as_ref())
+            .collect();
+        assert_eq!(
+            succeeded,
+            vec!["subscriber-0@example.com", "subscriber-2@example.com"]
+        );
+        assert_eq!(
+            report.succeeded[0].message.message_id.as_deref(),
+            Some("message-subscriber-0@example.com")
+        );
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(
+            report.failed[0].recipient.as_ref(),
+            "subscriber-1@example.com"
+        );
+        assert_eq!(
+            report.failed[0].error,
+            EmailError::InvalidRecipient(String::from(
+                "You tried to send to a recipient that has been marked as inactive."
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_does_not_send_emails_over_the_size_limit() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_email_client(url);
+
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(BatchResponder(vec![]))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let attachments = [Attachment {
+            content: vec![0; MAX_MESSAGE_SIZE],
+            ..set_inline_image()
+        }];
+        let mut batch = set_batch(2);
+        batch[0].options.attachments = &attachments;
+        let report = email_client.send_batch(batch).await;
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body.len(), 1);
+        assert_eq!(report.succeeded.len(), 1);
+        assert_eq!(
+            report.failed[0].recipient.as_ref(),
+            "subscriber-0@example.com"
+        );
+        assert!(matches!(
+            report.failed[0].error,
+            EmailError::MessageTooLarge { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_fails_every_message_of_a_rejected_chunk() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_email_client(url);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let report = email_client.send_batch(set_batch(3)).await;
+
+        assert!(report.succeeded.is_empty());
+        assert_eq!(report.failed.len(), 3);
+        assert!(report
+            .failed
+            .iter()
+            .all(|failed| matches!(failed.error, EmailError::Transport(_))));
+    }
+
+    #[tokio::test]
+    async fn test_send_email_retries_server_errors_until_it_succeeds() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_retrying_email_client(url);
+
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(send_any_email(&email_client).await);
+    }
+
+    #[tokio::test]
+    async fn test_send_email_retries_timeouts() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_retrying_email_client(url);
+
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180)))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(send_any_email(&email_client).await);
+    }
+
+    #[tokio::test]
+    async fn test_send_email_gives_up_after_max_attempts() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_retrying_email_client(url);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        assert_err!(send_any_email(&email_client).await);
+    }
+
+    #[tokio::test]
+    async fn test_send_email_does_not_retry_permanent_errors() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_retrying_email_client(url);
+
+        // Postmark's answer to an invalid recipient
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_err!(send_any_email(&email_client).await);
+    }
+
+    #[tokio::test]
+    async fn test_send_email_honours_retry_after() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_retrying_email_client(url);
+
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let start = std::time::Instant::now();
+        assert_ok!(send_any_email(&email_client).await);
+        assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_send_email_gives_up_when_retry_after_exceeds_the_max_delay() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_retrying_email_client(url);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_err!(send_any_email(&email_client).await);
+    }
+
+    #[tokio::test]
+    async fn test_send_email_waits_for_the_rate_limiter() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let transport = PostmarkTransport::new(
+            url,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        );
+        let email_client = EmailClient::new(
+            set_email(),
+            transport,
+            RetryPolicy::no_retries(),
+            RateLimiter::new(2.0, 1),
+        );
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..3 {
+            assert_ok!(send_any_email(&email_client).await);
+        }
+
+        // The bucket holds two messages, the third one waits half a second for a refill
+        let metrics = email_client.rate_limiter_metrics();
+        assert_eq!(metrics.permits, 3);
+        assert!(metrics.max_wait >= std::time::Duration::from_millis(400));
+    }
+
+    #[tokio::test]
+    async fn test_send_email_errors_tell_what_went_wrong() {
+        let test_cases = vec![
+            (
+                ResponseTemplate::new(422).set_body_json(serde_json::json!({
+                    "ErrorCode": 406,
+                    "Message": "You tried to send to a recipient that has been marked as inactive."
+                })),
+                EmailError::InvalidRecipient(String::from(
+                    "You tried to send to a recipient that has been marked as inactive.",
+                )),
+            ),
+            (
+                ResponseTemplate::new(422).set_body_json(serde_json::json!({
+                    "ErrorCode": 300,
+                    "Message": "Invalid email request"
+                })),
+                EmailError::ProviderError {
+                    code: 300,
+                    message: String::from("Invalid email request"),
+                },
+            ),
+            (
+                ResponseTemplate::new(401).set_body_json(serde_json::json!({
+                    "ErrorCode": 10,
+                    "Message": "No Account or Server API tokens were supplied in the HTTP headers."
+                })),
+                EmailError::Unauthorized,
+            ),
+            (
+                ResponseTemplate::new(429).insert_header("Retry-After", "30"),
+                EmailError::RateLimited {
+                    retry_after: Some(std::time::Duration::from_secs(30)),
+                },
+            ),
+            (
+                ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180)),
+                EmailError::Timeout,
+            ),
+        ];
+
+        for (response, expected_error) in test_cases {
+            let mock_server = MockServer::start().await;
+            let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+            let email_client = set_email_client(url);
+            Mock::given(any())
+                .respond_with(response)
+                .mount(&mock_server)
+                .await;
+
+            let error = send_any_email(&email_client).await.unwrap_err();
+
+            assert_eq!(error, expected_error);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_email_server_errors_are_transport_errors() {
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = set_email_client(url);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+
+        let error = send_any_email(&email_client).await.unwrap_err();
+
+        assert!(matches!(error, EmailError::Transport(_)));
+        assert!(!error.is_permanent());
+    }
 }
@@ -0,0 +1,86 @@
+use base64::Engine;
+
+use crate::email_client::EmailError;
+
+// Postmark refuses messages bigger than this, attachments included
+pub const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct Attachment {
+    pub name: String,
+    pub content_type: String,
+    pub content: Vec<u8>,
+    // Set for inline images, which the html body references as `cid:<content_id>`
+    pub content_id: Option<String>,
+}
+
+impl Attachment {
+    pub fn base64_content(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(&self.content)
+    }
+}
+
+// Size of the message as the provider sees it: attachments travel base64 encoded,
+// a third bigger than their raw content
+pub fn message_size(
+    html_body: &str,
+    text_body: &str,
+    attachment_sizes: impl IntoIterator<Item = usize>,
+) -> usize {
+    html_body.len()
+        + text_body.len()
+        + attachment_sizes
+            .into_iter()
+            .map(|size| size.div_ceil(3) * 4)
+            .sum::<usize>()
+}
+
+pub(crate) fn check_message_size(
+    html_body: &str,
+    text_body: &str,
+    attachments: &[Attachment],
+) -> Result<(), EmailError> {
+    let size = message_size(
+        html_body,
+        text_body,
+        attachments.iter().map(|a| a.content.len()),
+    );
+    if size > MAX_MESSAGE_SIZE {
+        return Err(EmailError::MessageTooLarge { size });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::{check_message_size, message_size, Attachment, MAX_MESSAGE_SIZE};
+
+    fn attachment(size: usize) -> Attachment {
+        Attachment {
+            name: String::from("cover.png"),
+            content_type: String::from("image/png"),
+            content: vec![0; size],
+            content_id: None,
+        }
+    }
+
+    #[test]
+    fn attachments_count_with_their_base64_size() {
+        assert_eq!(message_size("<p>Hi</p>", "Hi", [3, 4]), 9 + 2 + 4 + 8);
+    }
+
+    #[test]
+    fn messages_up_to_the_limit_are_accepted() {
+        let body = "a".repeat(MAX_MESSAGE_SIZE - 4);
+        assert_ok!(check_message_size(&body, "", &[attachment(3)]));
+    }
+
+    #[test]
+    fn messages_over_the_limit_are_rejected() {
+        // 7.5 MB of attachment is 10 MB once encoded
+        let attachment = attachment(MAX_MESSAGE_SIZE / 4 * 3);
+        assert_err!(check_message_size("<p>Hi</p>", "Hi", &[attachment]));
+    }
+}
@@ -0,0 +1,34 @@
+use std::path::Path;
+
+use anyhow::Context;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use crate::email_client::{build_message, Email, EmailError, EmailTransport, SentMessage};
+
+// Writes every email as an .eml file to a directory instead of sending it, for development
+pub struct FileSinkTransport {
+    transport: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileSinkTransport {
+    pub fn new(directory: impl AsRef<Path>) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self {
+            transport: AsyncFileTransport::new(directory),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for FileSinkTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<SentMessage, EmailError> {
+        let message = build_message(email)?;
+        let sent = SentMessage::from_message(&message);
+        self.transport
+            .send(message)
+            .await
+            .context("Failed to write the email to disk.")
+            .map_err(EmailError::transport)?;
+        Ok(sent)
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, StatusCode};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::email_client::{Email, EmailError, EmailTransport, SentMessage};
+
+// Postmark refuses batches with more messages than this
+const MAX_BATCH_SIZE: usize = 500;
+
+const INVALID_API_TOKEN: i64 = 10;
+const INACTIVE_RECIPIENT: i64 = 406;
+
+// Sends emails through Postmark's HTTP API
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: reqwest::Url,
+    authorization_token: Secret<String>,
+}
+
+impl PostmarkTransport {
+    pub fn new(
+        base_url: reqwest::Url,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        Self {
+            http_client: Client::builder()
+                .timeout(timeout)
+                .build()
+                .expect("Unable to build the HTTP client."),
+            base_url,
+            authorization_token,
+        }
+    }
+
+    async fn post<Body: serde::Serialize>(
+        &self,
+        path: &str,
+        body: &Body,
+    ) -> Result<reqwest::Response, EmailError> {
+        let url = reqwest::Url::join(&self.base_url, path)
+            .unwrap_or_else(|_| panic!("Unable to create {} url.", path));
+        let response = self
+            .http_client
+            .post(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(body)
+            .send()
+            .await
+            .map_err(|e| {
+                if e.is_timeout() {
+                    EmailError::Timeout
+                } else {
+                    EmailError::transport(
+                        anyhow::Error::new(e).context("Failed to reach Postmark."),
+                    )
+                }
+            })?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => Err(EmailError::RateLimited {
+                retry_after: retry_after(response.headers()),
+            }),
+            StatusCode::UNAUTHORIZED => Err(EmailError::Unauthorized),
+            _ if status.is_server_error() => Err(EmailError::Transport(format!(
+                "Postmark failed with status {}.",
+                status
+            ))),
+            // Postmark explains what is wrong with the request in the body
+            _ => {
+                let body = response.text().await.unwrap_or_default();
+                match serde_json::from_str::<PostmarkResult>(&body) {
+                    Ok(result) => Err(provider_error(result.error_code, result.message)),
+                    Err(_) => Err(EmailError::ProviderError {
+                        code: status.as_u16().into(),
+                        message: body,
+                    }),
+                }
+            }
+        }
+    }
+
+    async fn send_chunk(
+        &self,
+        emails: &[Email<'_>],
+    ) -> Result<Vec<Result<SentMessage, EmailError>>, EmailError> {
+        let request_body: Vec<SendEmailRequest> =
+            emails.iter().map(SendEmailRequest::from).collect();
+        let results: Vec<PostmarkResult> = self
+            .post("/email/batch", &request_body)
+            .await?
+            .json()
+            .await
+            .context("Failed to parse Postmark's batch response.")
+            .map_err(EmailError::transport)?;
+        if results.len() != emails.len() {
+            return Err(EmailError::Transport(format!(
+                "Postmark returned {} results for a batch of {} emails.",
+                results.len(),
+                emails.len()
+            )));
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|result| match result.error_code {
+                0 => Ok(result.into()),
+                error_code => Err(provider_error(error_code, result.message)),
+            })
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<SentMessage, EmailError> {
+        let response = self.post("/email", &SendEmailRequest::from(email)).await?;
+        // The email is on its way, failing now would only get it sent twice
+        match response.json::<PostmarkResult>().await {
+            Ok(result) => Ok(result.into()),
+            Err(e) => {
+                tracing::warn!("Failed to parse Postmark's response: {:?}", e);
+                Ok(SentMessage {
+                    message_id: None,
+                    submitted_at: chrono::Utc::now(),
+                    error_code: 0,
+                })
+            }
+        }
+    }
+
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<SentMessage, EmailError>> {
+        let mut results = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(chunk_results) => results.extend(chunk_results),
+                // Nothing in the chunk was accepted, e.g. Postmark could not be reached
+                Err(e) => results.extend(chunk.iter().map(|_| Err(e.clone()))),
+            }
+        }
+        results
+    }
+}
+
+// https://postmarkapp.com/developer/api/overview#error-codes
+fn provider_error(error_code: i64, message: String) -> EmailError {
+    match error_code {
+        INVALID_API_TOKEN => EmailError::Unauthorized,
+        INACTIVE_RECIPIENT => EmailError::InvalidRecipient(message),
+        code => EmailError::ProviderError { code, message },
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to: Option<&'a str>,
+    // Comma separated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bcc: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: &'a BTreeMap<&'a str, String>,
+    message_stream: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<PostmarkHeader<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<PostmarkAttachment<'a>>,
+}
+
+// Retry-After holds either a number of seconds or an HTTP date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means right away
+    Some(
+        (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+impl<'a> From<&'a Email<'a>> for SendEmailRequest<'a> {
+    fn from(email: &'a Email<'a>) -> Self {
+        Self {
+            from: email.from,
+            to: email.to,
+            subject: email.subject,
+            html_body: email.html_body,
+            text_body: email.text_body,
+            reply_to: email.options.reply_to,
+            cc: addresses(&email.options.cc),
+            bcc: addresses(&email.options.bcc),
+            tag: email.options.tag,
+            metadata: &email.options.metadata,
+            message_stream: email.options.message_stream.as_str(),
+            headers: email
+                .headers
+                .iter()
+                .map(|header| PostmarkHeader {
+                    name: header.name,
+                    value: &header.value,
+                })
+                .collect(),
+            attachments: email
+                .options
+                .attachments
+                .iter()
+                .map(|attachment| PostmarkAttachment {
+                    name: &attachment.name,
+                    content: attachment.base64_content(),
+                    content_type: &attachment.content_type,
+                    content_id: attachment
+                        .content_id
+                        .as_ref()
+                        .map(|content_id| format!("cid:{}", content_id)),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn addresses(addresses: &[&str]) -> Option<String> {
+    if addresses.is_empty() {
+        return None;
+    }
+    Some(addresses.join(", "))
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkHeader<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkAttachment<'a> {
+    name: &'a str,
+    // Base64 encoded
+    content: String,
+    content_type: &'a str,
+    #[serde(rename = "ContentID", skip_serializing_if = "Option::is_none")]
+    content_id: Option<String>,
+}
+
+// Body of Postmark's responses, one per message for batches. ErrorCode 0 means accepted
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkResult {
+    error_code: i64,
+    message: String,
+    // Only there for accepted emails
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    submitted_at: Option<chrono::DateTime<chrono::FixedOffset>>,
+}
+
+impl From<PostmarkResult> for SentMessage {
+    fn from(result: PostmarkResult) -> Self {
+        Self {
+            message_id: result.message_id,
+            submitted_at: result
+                .submitted_at
+                .map(|submitted_at| submitted_at.with_timezone(&chrono::Utc))
+                .unwrap_or_else(chrono::Utc::now),
+            error_code: result.error_code,
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use tokio::sync::{Mutex, Semaphore, SemaphorePermit};
+use tokio::time::Instant;
+
+// Keeps us under the provider's throttling limits: a token bucket refilled at
+// `messages_per_second`, holding at most one second worth of messages, plus a cap on the
+// requests in flight at any time
+pub struct RateLimiter {
+    messages_per_second: f64,
+    bucket: Mutex<Bucket>,
+    in_flight: Semaphore,
+    metrics: std::sync::Mutex<RateLimiterMetrics>,
+}
+
+struct Bucket {
+    // Negative when a batch took more messages than were available
+    tokens: f64,
+    last_refill: Instant,
+}
+
+// How long sends had to wait before being allowed through, since startup
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RateLimiterMetrics {
+    pub permits: u64,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+}
+
+impl RateLimiterMetrics {
+    pub fn average_wait(&self) -> Duration {
+        match self.permits {
+            0 => Duration::ZERO,
+            permits => self.total_wait / permits as u32,
+        }
+    }
+}
+
+// Lets a request through while alive, counting towards the concurrency limit
+#[derive(Debug)]
+pub struct Permit<'a> {
+    _in_flight: SemaphorePermit<'a>,
+}
+
+impl RateLimiter {
+    pub fn new(messages_per_second: f64, max_concurrent_requests: usize) -> Self {
+        assert!(
+            messages_per_second > 0.0,
+            "The email rate limit must be a positive number of messages per second."
+        );
+        Self {
+            messages_per_second,
+            bucket: Mutex::new(Bucket {
+                tokens: messages_per_second.max(1.0),
+                last_refill: Instant::now(),
+            }),
+            in_flight: Semaphore::new(max_concurrent_requests),
+            metrics: std::sync::Mutex::new(RateLimiterMetrics::default()),
+        }
+    }
+
+    // Waits until a request carrying `messages` emails can go out
+    pub async fn acquire(&self, messages: usize) -> Permit<'_> {
+        let start = Instant::now();
+        let in_flight = self
+            .in_flight
+            .acquire()
+            .await
+            .expect("The rate limiter semaphore is never closed.");
+        {
+            // Waiting with the lock held queues the other senders behind us, in order
+            let mut bucket = self.bucket.lock().await;
+            self.refill(&mut bucket);
+            bucket.tokens -= messages as f64;
+            if bucket.tokens < 0.0 {
+                let deficit = Duration::from_secs_f64(-bucket.tokens / self.messages_per_second);
+                tokio::time::sleep(deficit).await;
+            }
+        }
+
+        let waited = start.elapsed();
+        self.record_wait(waited);
+        tracing::debug!(
+            permit_wait_ms = waited.as_millis() as u64,
+            "Acquired a permit to send emails"
+        );
+        Permit {
+            _in_flight: in_flight,
+        }
+    }
+
+    pub fn metrics(&self) -> RateLimiterMetrics {
+        *self.metrics.lock().unwrap()
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        let capacity = self.messages_per_second.max(1.0);
+        bucket.tokens = (bucket.tokens + elapsed * self.messages_per_second).min(capacity);
+        bucket.last_refill = now;
+    }
+
+    fn record_wait(&self, waited: Duration) {
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.permits += 1;
+        metrics.total_wait += waited;
+        metrics.max_wait = metrics.max_wait.max(waited);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claims::{assert_err, assert_ok};
+    use tokio::time::{timeout, Instant};
+
+    use super::RateLimiter;
+
+    #[tokio::test]
+    async fn a_full_bucket_lets_a_burst_through_right_away() {
+        let rate_limiter = RateLimiter::new(10.0, 10);
+
+        let start = Instant::now();
+        for _ in 0..10 {
+            rate_limiter.acquire(1).await;
+        }
+
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn an_empty_bucket_waits_for_a_refill() {
+        let rate_limiter = RateLimiter::new(10.0, 10);
+        rate_limiter.acquire(10).await;
+
+        let start = Instant::now();
+        rate_limiter.acquire(1).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn batches_wait_for_every_message_they_carry() {
+        let rate_limiter = RateLimiter::new(100.0, 10);
+
+        let start = Instant::now();
+        rate_limiter.acquire(120).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_concurrency_limit_wait_for_a_permit() {
+        let rate_limiter = RateLimiter::new(100.0, 1);
+
+        let permit = rate_limiter.acquire(1).await;
+        assert_err!(timeout(Duration::from_millis(50), rate_limiter.acquire(1)).await);
+
+        drop(permit);
+        assert_ok!(timeout(Duration::from_millis(50), rate_limiter.acquire(1)).await);
+    }
+
+    #[tokio::test]
+    async fn metrics_record_how_long_sends_waited() {
+        let rate_limiter = RateLimiter::new(10.0, 10);
+        rate_limiter.acquire(10).await;
+        rate_limiter.acquire(1).await;
+
+        let metrics = rate_limiter.metrics();
+
+        assert_eq!(metrics.permits, 2);
+        assert!(metrics.max_wait >= Duration::from_millis(90));
+        assert!(metrics.total_wait >= metrics.max_wait);
+        assert_eq!(metrics.average_wait(), metrics.total_wait / 2);
+    }
+}
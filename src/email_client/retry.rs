@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+// How EmailClient retries deliveries that failed for a transient reason
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // Including the first attempt, 1 disables retries
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Fraction of each delay that is randomised, so that clients failing at the same time
+    // don't retry in lockstep. Between 0 and 1
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    pub fn no_retries() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: 0.0,
+        }
+    }
+
+    // Delay before the next attempt, given how many attempts have been made so far.
+    // None when the email should not be retried any more
+    pub fn delay_after(&self, attempts: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        match retry_after {
+            // The provider knows best, but waiting longer than we are willing to is
+            // no better than giving up
+            Some(retry_after) if retry_after > self.max_delay => None,
+            Some(retry_after) => Some(retry_after),
+            None => Some(self.backoff(attempts)),
+        }
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)));
+        let delay = exponential.min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 - jitter * rand::thread_rng().gen::<f64>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claims::{assert_none, assert_some_eq};
+
+    use super::RetryPolicy;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(250),
+            jitter,
+        }
+    }
+
+    #[test]
+    fn delay_doubles_with_every_attempt_up_to_the_max_delay() {
+        let policy = policy(0.0);
+
+        assert_some_eq!(policy.delay_after(1, None), Duration::from_millis(100));
+        assert_some_eq!(policy.delay_after(2, None), Duration::from_millis(200));
+        assert_some_eq!(policy.delay_after(3, None), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn no_delay_once_the_attempts_are_exhausted() {
+        assert_none!(policy(0.0).delay_after(4, None));
+        assert_none!(RetryPolicy::no_retries().delay_after(1, None));
+    }
+
+    #[test]
+    fn jitter_only_shortens_the_delay() {
+        let policy = policy(0.5);
+
+        for _ in 0..100 {
+            let delay = policy.delay_after(2, None).unwrap();
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn retry_after_replaces_the_backoff() {
+        let policy = policy(0.5);
+
+        assert_some_eq!(
+            policy.delay_after(1, Some(Duration::from_millis(230))),
+            Duration::from_millis(230)
+        );
+    }
+
+    #[test]
+    fn retry_after_longer_than_the_max_delay_gives_up() {
+        assert_none!(policy(0.0).delay_after(1, Some(Duration::from_secs(60))));
+    }
+}
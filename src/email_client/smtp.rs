@@ -0,0 +1,191 @@
+use anyhow::Context;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::email_client::{build_message, Email, EmailError, EmailTransport, SentMessage};
+
+// RFC 5321 reply codes
+const AUTHENTICATION_FAILED: i64 = 535;
+const MAILBOX_UNAVAILABLE: i64 = 550;
+const MAILBOX_NAME_NOT_ALLOWED: i64 = 553;
+
+// Sends emails to an SMTP relay, authenticating with username and password
+pub struct SmtpTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        host: &str,
+        port: u16,
+        username: String,
+        password: Secret<String>,
+        starttls: bool,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let builder = if starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .context("Failed to set up TLS for the SMTP relay.")?
+        } else {
+            // Unencrypted, only acceptable for relays on a trusted network such as a local
+            // mail catcher
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+        let transport = builder
+            .port(port)
+            .credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ))
+            .timeout(Some(timeout))
+            .build();
+        Ok(Self { transport })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<SentMessage, EmailError> {
+        let message = build_message(email)?;
+        let sent = SentMessage::from_message(&message);
+        self.transport.send(message).await.map_err(smtp_error)?;
+        Ok(sent)
+    }
+}
+
+fn smtp_error(e: lettre::transport::smtp::Error) -> EmailError {
+    if e.is_timeout() {
+        return EmailError::Timeout;
+    }
+    let code = e
+        .status()
+        .and_then(|code| code.to_string().parse::<i64>().ok());
+    match code {
+        Some(AUTHENTICATION_FAILED) => EmailError::Unauthorized,
+        Some(MAILBOX_UNAVAILABLE | MAILBOX_NAME_NOT_ALLOWED) => {
+            EmailError::InvalidRecipient(e.to_string())
+        }
+        // 4xx replies are transient, the relay may take the email later
+        Some(code) if code >= 500 => EmailError::ProviderError {
+            code,
+            message: e.to_string(),
+        },
+        _ => EmailError::transport(
+            anyhow::Error::new(e).context("The SMTP relay did not accept the email."),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_matches, assert_ok};
+    use secrecy::Secret;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use crate::email_client::{Email, EmailError, EmailOptions, EmailTransport, SmtpTransport};
+
+    // A relay accepting everything but `refused`, a command and the reply it gets. The end of
+    // the email content is the "." command
+    async fn fake_relay(refused: Option<(&'static str, &'static str)>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (read, mut write) = socket.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write.write_all(b"220 relay.test ESMTP\r\n").await.unwrap();
+            let mut in_data = false;
+            while let Ok(Some(line)) = lines.next_line().await {
+                let command = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    String::from(".")
+                } else {
+                    line.split_whitespace().next().unwrap_or("").to_uppercase()
+                };
+                let answer = match (command.as_str(), refused) {
+                    (command, Some((refused, reply))) if command == refused => reply,
+                    ("EHLO", _) => "250-relay.test\r\n250 AUTH PLAIN LOGIN",
+                    ("AUTH", _) => "235 Authenticated",
+                    ("DATA", _) => "354 Go ahead",
+                    ("QUIT", _) => "221 Bye",
+                    _ => "250 OK",
+                };
+                in_data = answer.starts_with("354");
+                write
+                    .write_all(format!("{}\r\n", answer).as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+        port
+    }
+
+    async fn send_through(port: u16) -> Result<(), EmailError> {
+        let transport = SmtpTransport::new(
+            "127.0.0.1",
+            port,
+            String::from("user"),
+            Secret::new(String::from("password")),
+            false,
+            std::time::Duration::from_secs(2),
+        )
+        .unwrap();
+        let options = EmailOptions::default();
+        transport
+            .send(&Email {
+                from: "sender@example.com",
+                to: "recipient@example.com",
+                subject: "Subject",
+                html_body: "<p>Content</p>",
+                text_body: "Content",
+                headers: vec![],
+                options: &options,
+            })
+            .await
+            .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn accepted_emails_are_sent() {
+        let port = fake_relay(None).await;
+        assert_ok!(send_through(port).await);
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials_are_unauthorized() {
+        let port = fake_relay(Some(("AUTH", "535 Authentication failed"))).await;
+        assert_matches!(send_through(port).await, Err(EmailError::Unauthorized));
+    }
+
+    #[tokio::test]
+    async fn unknown_mailboxes_are_invalid_recipients() {
+        for reply in ["550 No such user", "553 Mailbox name not allowed"] {
+            let port = fake_relay(Some(("RCPT", reply))).await;
+            assert_matches!(
+                send_through(port).await,
+                Err(EmailError::InvalidRecipient(_))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn other_permanent_replies_are_provider_errors() {
+        let port = fake_relay(Some((".", "554 Message rejected"))).await;
+        assert_matches!(
+            send_through(port).await,
+            Err(EmailError::ProviderError { code: 554, .. })
+        );
+    }
+
+    #[tokio::test]
+    async fn transient_replies_are_transport_errors_to_retry() {
+        let port = fake_relay(Some((".", "451 Try again later"))).await;
+        let outcome = send_through(port).await;
+        assert_matches!(outcome, Err(EmailError::Transport(_)));
+        assert!(!outcome.unwrap_err().is_permanent());
+    }
+}
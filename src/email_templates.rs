@@ -0,0 +1,302 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+// Variables each kind of email offers its templates, and those the templates can't do without
+const CONFIRMATION_VARIABLES: [&str; 2] = ["name", "confirm_url"];
+const CONFIRMATION_REQUIRED: [&str; 1] = ["confirm_url"];
+const NEWSLETTER_VARIABLES: [&str; 4] = ["name", "title", "content", "unsubscribe_url"];
+const NEWSLETTER_REQUIRED: [&str; 2] = ["content", "unsubscribe_url"];
+
+// Body of an email, in both formats
+pub struct RenderedEmail {
+    pub html: String,
+    pub text: String,
+}
+
+// The templates of every email we send, read from `<name>.html` and `<name>.txt` files.
+// Loaded once at startup: a template using a variable its email doesn't provide, or missing
+// one it can't do without, stops the application from starting
+pub struct EmailTemplates {
+    confirmation: TemplatePair,
+    newsletter: TemplatePair,
+}
+
+impl EmailTemplates {
+    pub fn load(directory: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let directory = directory.as_ref();
+        Ok(Self {
+            confirmation: TemplatePair::load(
+                directory,
+                "confirmation",
+                &CONFIRMATION_VARIABLES,
+                &CONFIRMATION_REQUIRED,
+            )?,
+            newsletter: TemplatePair::load(
+                directory,
+                "newsletter",
+                &NEWSLETTER_VARIABLES,
+                &NEWSLETTER_REQUIRED,
+            )?,
+        })
+    }
+
+    pub fn confirmation(&self, name: &str, confirm_url: &str) -> RenderedEmail {
+        self.confirmation.render(&[
+            ("name", Value::Text(name)),
+            ("confirm_url", Value::Text(confirm_url)),
+        ])
+    }
+
+    pub fn newsletter(
+        &self,
+        name: &str,
+        title: &str,
+        html_content: &str,
+        text_content: &str,
+        unsubscribe_url: &str,
+    ) -> RenderedEmail {
+        self.newsletter.render(&[
+            ("name", Value::Text(name)),
+            ("title", Value::Text(title)),
+            (
+                "content",
+                Value::Markup {
+                    html: html_content,
+                    text: text_content,
+                },
+            ),
+            ("unsubscribe_url", Value::Text(unsubscribe_url)),
+        ])
+    }
+}
+
+// What a variable stands for
+enum Value<'a> {
+    // Escaped in html templates, names and urls can't inject markup
+    Text(&'a str),
+    // Trusted markup, used as is. Text templates get its plain text version
+    Markup { html: &'a str, text: &'a str },
+}
+
+struct TemplatePair {
+    html: Template,
+    text: Template,
+}
+
+impl TemplatePair {
+    fn load(
+        directory: &Path,
+        name: &str,
+        variables: &[&str],
+        required: &[&str],
+    ) -> Result<Self, anyhow::Error> {
+        let load = |extension: &str| -> Result<Template, anyhow::Error> {
+            let path = directory.join(format!("{}.{}", name, extension));
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read the {} template.", path.display()))?;
+            let template = Template::parse(&source)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("Invalid template {}.", path.display()))?;
+            template
+                .validate(variables, required)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("Invalid template {}.", path.display()))?;
+            Ok(template)
+        };
+        Ok(Self {
+            html: load("html")?,
+            text: load("txt")?,
+        })
+    }
+
+    fn render(&self, values: &[(&str, Value)]) -> RenderedEmail {
+        RenderedEmail {
+            html: self.html.render(|variable| match lookup(values, variable) {
+                Value::Text(text) => htmlescape::encode_minimal(text),
+                Value::Markup { html, .. } => html.to_string(),
+            }),
+            text: self.text.render(|variable| match lookup(values, variable) {
+                Value::Text(text) | Value::Markup { text, .. } => text.to_string(),
+            }),
+        }
+    }
+}
+
+// Templates were checked against the variables of their email when loaded
+fn lookup<'a>(values: &'a [(&str, Value<'a>)], variable: &str) -> &'a Value<'a> {
+    values
+        .iter()
+        .find(|(name, _)| *name == variable)
+        .map(|(_, value)| value)
+        .unwrap_or_else(|| panic!("No value for the {} template variable.", variable))
+}
+
+// Text with `{{variable}}` placeholders
+#[derive(Debug, PartialEq)]
+struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Variable(String),
+}
+
+impl Template {
+    fn parse(source: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let after_braces = &rest[start + 2..];
+            let end = after_braces
+                .find("}}")
+                .ok_or_else(|| String::from("A {{ is never closed."))?;
+            let variable = after_braces[..end].trim();
+            if variable.is_empty()
+                || !variable
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                return Err(format!("{{{{{}}}}} is not a valid variable.", variable));
+            }
+            parts.push(Part::Variable(variable.to_string()));
+            rest = &after_braces[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Self { parts })
+    }
+
+    fn validate(&self, variables: &[&str], required: &[&str]) -> Result<(), String> {
+        for part in &self.parts {
+            if let Part::Variable(variable) = part {
+                if !variables.contains(&variable.as_str()) {
+                    return Err(format!(
+                        "{{{{{}}}}} is not available, use one of {}.",
+                        variable,
+                        variables.join(", ")
+                    ));
+                }
+            }
+        }
+        for variable in required {
+            if !self
+                .parts
+                .iter()
+                .any(|part| matches!(part, Part::Variable(v) if v == variable))
+            {
+                return Err(format!("{{{{{}}}}} is missing.", variable));
+            }
+        }
+        Ok(())
+    }
+
+    fn render(&self, mut value: impl FnMut(&str) -> String) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Variable(variable) => rendered.push_str(&value(variable)),
+            }
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::{EmailTemplates, Part, Template, TemplatePair};
+
+    fn pair(html: &str, text: &str) -> TemplatePair {
+        TemplatePair {
+            html: Template::parse(html).unwrap(),
+            text: Template::parse(text).unwrap(),
+        }
+    }
+
+    #[test]
+    fn variables_are_parsed_with_or_without_spaces() {
+        let template = Template::parse("Hi {{name}}, {{ confirm_url }}!").unwrap();
+
+        assert_eq!(
+            template.parts,
+            vec![
+                Part::Text(String::from("Hi ")),
+                Part::Variable(String::from("name")),
+                Part::Text(String::from(", ")),
+                Part::Variable(String::from("confirm_url")),
+                Part::Text(String::from("!")),
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_or_malformed_variables_are_rejected() {
+        assert_err!(Template::parse("Hi {{name"));
+        assert_err!(Template::parse("Hi {{}}"));
+        assert_err!(Template::parse("Hi {{first name}}"));
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        let template = Template::parse("Hi {{nmae}}, {{confirm_url}}").unwrap();
+
+        assert_err!(template.validate(&["name", "confirm_url"], &[]));
+    }
+
+    #[test]
+    fn templates_missing_a_required_variable_are_rejected() {
+        let template = Template::parse("Hi {{name}}").unwrap();
+
+        assert_err!(template.validate(&["name", "confirm_url"], &["confirm_url"]));
+        assert_ok!(template.validate(&["name", "confirm_url"], &[]));
+    }
+
+    #[test]
+    fn text_values_are_escaped_in_html_only() {
+        let templates = EmailTemplates {
+            confirmation: pair("<p>Hi {{name}}</p> {{confirm_url}}", "Hi {{name}}"),
+            newsletter: pair("{{content}} {{unsubscribe_url}}", "{{content}}"),
+        };
+
+        let email = templates.confirmation("Tom & Jerry's", "https://my-api.com");
+
+        assert_eq!(
+            email.html,
+            "<p>Hi Tom &amp; Jerry&#x27;s</p> https://my-api.com"
+        );
+        assert_eq!(email.text, "Hi Tom & Jerry's");
+    }
+
+    #[test]
+    fn markup_is_used_as_is_and_text_templates_get_the_text_version() {
+        let templates = EmailTemplates {
+            confirmation: pair("{{confirm_url}}", "{{confirm_url}}"),
+            newsletter: pair("{{content}} {{unsubscribe_url}}", "{{content}}"),
+        };
+
+        let email = templates.newsletter(
+            "le guin",
+            "Title",
+            "<p>Issue</p>",
+            "Issue",
+            "https://my-api.com/unsubscribe",
+        );
+
+        assert_eq!(email.html, "<p>Issue</p> https://my-api.com/unsubscribe");
+        assert_eq!(email.text, "Issue");
+    }
+
+    #[test]
+    fn the_shipped_templates_are_valid() {
+        assert_ok!(EmailTemplates::load("templates"));
+    }
+}
@@ -0,0 +1,10 @@
+mod key;
+mod persistence;
+
+use uuid::Uuid;
+
+pub use key::{get_idempotency_key, IdempotencyKey};
+pub use persistence::{discard_saved_response, save_response, try_processing, NextAction};
+
+// Owner of the keys sent by requests made without a logged-in user
+pub const ANONYMOUS_USER_ID: Uuid = Uuid::nil();
@@ -0,0 +1,109 @@
+use actix_web::HttpRequest;
+use sha2::{Digest, Sha256};
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 50;
+
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl IdempotencyKey {
+    pub fn parse(s: String) -> Result<IdempotencyKey, String> {
+        if s.trim().is_empty() {
+            return Err(String::from("The idempotency key cannot be empty"));
+        }
+
+        if s.len() >= MAX_IDEMPOTENCY_KEY_LENGTH {
+            return Err(format!(
+                "The idempotency key must be shorter than {} characters",
+                MAX_IDEMPOTENCY_KEY_LENGTH
+            ));
+        }
+
+        Ok(Self(s))
+    }
+
+    // Anonymous callers all share one namespace, where two of them may well pick the same
+    // key. Tied to the endpoint and the request it came with, the same key sent for something
+    // else is a different key
+    pub fn fingerprinted(&self, endpoint: &str, request: &[&str]) -> IdempotencyKey {
+        let mut hasher = Sha256::new();
+        hasher.update(endpoint);
+        for part in request {
+            // Separated, so that parts can't run into each other
+            hasher.update([0]);
+            hasher.update(part);
+        }
+        Self(format!("{}:{}", self.0, hex::encode(hasher.finalize())))
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// The header is optional: requests without it are processed every time they are received
+pub fn get_idempotency_key(request: &HttpRequest) -> Result<Option<IdempotencyKey>, String> {
+    match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        None => Ok(None),
+        Some(value) => {
+            let value = value
+                .to_str()
+                .map_err(|_| String::from("The idempotency key has to be a valid string"))?;
+            IdempotencyKey::parse(value.to_string()).map(Some)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::idempotency::IdempotencyKey;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn test_empty_string_invalid() {
+        let key = String::from("");
+        assert_err!(IdempotencyKey::parse(key));
+    }
+
+    #[test]
+    fn test_whitespace_only_invalid() {
+        let key = String::from(" ");
+        assert_err!(IdempotencyKey::parse(key));
+    }
+
+    #[test]
+    fn test_50_characters_long_key_invalid() {
+        let key = "a".repeat(50);
+        assert_err!(IdempotencyKey::parse(key));
+    }
+
+    #[test]
+    fn test_uuid_key_valid() {
+        let key = uuid::Uuid::new_v4().to_string();
+        assert_ok!(IdempotencyKey::parse(key));
+    }
+
+    #[test]
+    fn test_fingerprinted_keys_differ_by_request() {
+        let key = IdempotencyKey::parse(String::from("retry-1")).unwrap();
+        let fingerprinted =
+            |endpoint, request: &[&str]| key.fingerprinted(endpoint, request).as_ref().to_owned();
+        let original = fingerprinted("/subscriptions", &["a@example.com", "a"]);
+        assert_eq!(
+            original,
+            fingerprinted("/subscriptions", &["a@example.com", "a"])
+        );
+        assert_ne!(
+            original,
+            fingerprinted("/subscriptions", &["b@example.com", "a"])
+        );
+        assert_ne!(original, fingerprinted("/other", &["a@example.com", "a"]));
+        assert_ne!(
+            original,
+            fingerprinted("/subscriptions", &["a@example.coma", ""])
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use actix_web::web::Data;
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::{Attachment, EmailClient, EmailOptions, MessageStream};
+use crate::email_templates::EmailTemplates;
+use crate::routes::subscriptions_unsubscribe::unsubscribe_link;
+use crate::sent_messages::record_sent_message;
+
+// Deliveries failing more times than this are dropped for good
+const MAX_RETRIES: i32 = 5;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    subscriber_email: String,
+    subscriber_name: String,
+    subscriber_status: String,
+    subscriber_suppressed: bool,
+    n_retries: i32,
+}
+
+pub struct Issue {
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+}
+
+// Issues can no longer change once they are being sent. Each one is loaded, images included,
+// for its first delivery and reused for the others. Dropped whenever the queue runs dry
+#[derive(Default)]
+pub struct IssueCache {
+    issues: HashMap<Uuid, CachedIssue>,
+}
+
+struct CachedIssue {
+    issue: Issue,
+    images: Vec<Attachment>,
+}
+
+impl IssueCache {
+    async fn get(&mut self, pool: &PgPool, issue_id: Uuid) -> Result<&CachedIssue, sqlx::Error> {
+        let cached = match self.issues.entry(issue_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let issue = get_issue(pool, issue_id).await?;
+                let images = get_issue_images(pool, issue_id).await?;
+                entry.insert(CachedIssue { issue, images })
+            }
+        };
+        Ok(cached)
+    }
+}
+
+pub async fn run_worker_until_stopped(
+    pool: PgPool,
+    email_client: Data<EmailClient>,
+    email_templates: Data<EmailTemplates>,
+    base_url: String,
+    hmac_secret: Secret<String>,
+) -> Result<(), std::io::Error> {
+    let mut issue_cache = IssueCache::default();
+    loop {
+        match try_execute_task(
+            &pool,
+            &email_client,
+            &email_templates,
+            &base_url,
+            &hmac_secret,
+            &mut issue_cache,
+        )
+        .await
+        {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                // Once per pass over the queue rather than after every delivery
+                if let Err(e) = mark_delivered_issues(&pool).await {
+                    tracing::error!("Failed to mark the delivered issues as sent: {:?}", e);
+                }
+                tokio::time::sleep(Duration::from_secs(10)).await
+            }
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(newsletter_issue_id=tracing::field::Empty, subscriber_id=tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    email_templates: &EmailTemplates,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    issue_cache: &mut IssueCache,
+) -> Result<ExecutionOutcome, sqlx::Error> {
+    // The row stays locked until the transaction is committed. If the worker dies
+    // mid-send the transaction is rolled back and the task becomes visible again
+    let (mut transaction, task) = match dequeue_task(pool).await? {
+        Some(dequeued) => dequeued,
+        None => {
+            issue_cache.issues.clear();
+            return Ok(ExecutionOutcome::EmptyQueue);
+        }
+    };
+    tracing::Span::current()
+        .record(
+            "newsletter_issue_id",
+            tracing::field::display(task.newsletter_issue_id),
+        )
+        .record("subscriber_id", tracing::field::display(task.subscriber_id));
+
+    // Subscribers might have left the list, or been suppressed, after the issue was published
+    if task.subscriber_status != "confirmed" || task.subscriber_suppressed {
+        delete_task(&mut transaction, &task).await?;
+        transaction.commit().await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+
+    match SubscriberEmail::parse(task.subscriber_email.clone()) {
+        Ok(email) => {
+            let CachedIssue { issue, images } =
+                issue_cache.get(pool, task.newsletter_issue_id).await?;
+            let unsubscribe_link = unsubscribe_link(base_url, task.subscriber_id, hmac_secret);
+            let body = email_templates.newsletter(
+                &task.subscriber_name,
+                &issue.title,
+                &issue.html_content,
+                &issue.text_content,
+                &unsubscribe_link,
+            );
+            let options = EmailOptions {
+                message_stream: MessageStream::Broadcast,
+                tag: Some("newsletter"),
+                metadata: [
+                    ("newsletter_issue_id", task.newsletter_issue_id.to_string()),
+                    ("subscriber_id", task.subscriber_id.to_string()),
+                ]
+                .into(),
+                attachments: images,
+                ..Default::default()
+            };
+            match email_client
+                .send_email_with_options(
+                    email,
+                    &issue.title,
+                    &body.html,
+                    &body.text,
+                    Some(&unsubscribe_link),
+                    &options,
+                )
+                .await
+            {
+                Ok(sent) => {
+                    // Outside of the transaction: the email is gone either way, losing track
+                    // of it must not get it sent again
+                    if let Err(e) = record_sent_message(
+                        pool,
+                        &task.subscriber_email,
+                        Some(task.subscriber_id),
+                        Some(task.newsletter_issue_id),
+                        &sent,
+                    )
+                    .await
+                    {
+                        tracing::error!("Failed to record a delivered issue: {:?}", e);
+                    }
+                    delete_task(&mut transaction, &task).await?;
+                }
+                // Trying again later would be refused all the same
+                Err(e) if e.is_permanent() => {
+                    tracing::warn!(
+                        "The provider refused to deliver the issue to a confirmed subscriber. \
+                        Giving up: {:?}",
+                        e
+                    );
+                    delete_task(&mut transaction, &task).await?;
+                }
+                Err(e) if task.n_retries < MAX_RETRIES => {
+                    tracing::warn!(
+                        "Failed to deliver issue to a confirmed subscriber. Retrying later: {:?}",
+                        e
+                    );
+                    reschedule_task(&mut transaction, &task).await?;
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to deliver issue to a confirmed subscriber. Giving up: {:?}",
+                        e
+                    );
+                    delete_task(&mut transaction, &task).await?;
+                }
+            }
+        }
+        // An address that was valid when stored may not pass today's validation rules
+        Err(e) => {
+            tracing::warn!(
+                "Skipping a confirmed subscriber. Their stored contact details are invalid: {}",
+                e
+            );
+            delete_task(&mut transaction, &task).await?;
+        }
+    }
+    transaction.commit().await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+// Issues being sent are sent once their last delivery left the queue. Checked by the workers
+// whenever they find the queue empty, and by the scheduler on each of its rounds
+pub async fn mark_delivered_issues(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues i
+        SET status = 'sent'
+        WHERE status = 'sending'
+            AND NOT EXISTS (
+                SELECT 1 FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id
+            )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Enqueueing a delivery for every confirmed subscriber",
+    skip_all
+)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_id)
+        SELECT $1, id FROM subscriptions s
+        WHERE s.status = 'confirmed'
+            AND NOT EXISTS (SELECT 1 FROM suppressions p WHERE p.email = lower(s.email))
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(Transaction<'static, Postgres>, DeliveryTask)>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    // SKIP LOCKED lets several workers dequeue concurrently without picking the same task
+    let task = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT q.newsletter_issue_id, q.subscriber_id, q.n_retries,
+            s.email AS subscriber_email, s.name AS subscriber_name,
+            s.status AS subscriber_status,
+            EXISTS (
+                SELECT 1 FROM suppressions p WHERE p.email = lower(s.email)
+            ) AS "subscriber_suppressed!"
+        FROM issue_delivery_queue q
+        JOIN subscriptions s ON s.id = q.subscriber_id
+        WHERE q.execute_after <= now()
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    Ok(task.map(|task| (transaction, task)))
+}
+
+async fn delete_task(
+    transaction: &mut Transaction<'static, Postgres>,
+    task: &DeliveryTask,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+async fn reschedule_task(
+    transaction: &mut Transaction<'static, Postgres>,
+    task: &DeliveryTask,
+) -> Result<(), sqlx::Error> {
+    // Exponential backoff: 1, 2, 4, 8... minutes
+    let delay_seconds = 60.0 * 2f64.powi(task.n_retries);
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_id,
+        delay_seconds
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<Issue, sqlx::Error> {
+    let issue = sqlx::query_as!(
+        Issue,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(issue)
+}
+
+// Embedded in the html, each image is referenced by its id
+pub async fn get_issue_images(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Vec<Attachment>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT i.image_id, i.file_name, i.content_type, i.content
+        FROM newsletter_issue_images ii
+        JOIN newsletter_images i ON i.image_id = ii.image_id
+        WHERE ii.newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| Attachment {
+            name: r.file_name,
+            content_type: r.content_type,
+            content: r.content,
+            content_id: Some(r.image_id.to_string()),
+        })
+        .collect())
+}
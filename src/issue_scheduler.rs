@@ -0,0 +1,153 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, LocalResult, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::clock::Clock;
+use crate::issue_delivery_worker::{enqueue_delivery_tasks, mark_delivered_issues};
+
+// Accepted for schedule times given without an offset
+const LOCAL_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"];
+
+pub async fn run_scheduler_until_stopped(
+    pool: PgPool,
+    clock: Arc<dyn Clock>,
+) -> Result<(), std::io::Error> {
+    loop {
+        // A failed round is logged, the issues it missed are picked up by the next one
+        let _ = publish_due_issues(&pool, clock.as_ref()).await;
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+// Publishes the scheduled issues whose time has come, queueing a delivery for every confirmed
+// subscriber. Returns the ids of the issues published
+#[tracing::instrument(skip_all, err)]
+pub async fn publish_due_issues(
+    pool: &PgPool,
+    clock: &dyn Clock,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let now = clock.now();
+    let mut transaction = pool.begin().await?;
+    // SKIP LOCKED: issues being rescheduled, or published by another instance, are left alone
+    let due = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND scheduled_at <= $1
+        FOR UPDATE
+        SKIP LOCKED
+        "#,
+        now
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+
+    let mut published = Vec::with_capacity(due.len());
+    for issue in due {
+        start_sending(&mut transaction, issue.newsletter_issue_id, now).await?;
+        tracing::info!(
+            newsletter_issue_id = %issue.newsletter_issue_id,
+            "Published a scheduled newsletter issue"
+        );
+        published.push(issue.newsletter_issue_id);
+    }
+    transaction.commit().await?;
+    // Issues without any subscriber to deliver to are done already
+    mark_delivered_issues(pool).await?;
+    Ok(published)
+}
+
+// Moves a scheduled issue to sending, queueing its deliveries
+pub async fn start_sending(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    published_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    enqueue_delivery_tasks(transaction, newsletter_issue_id).await?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'sending', published_at = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        published_at
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+// Schedule times are wall clock times of the list's timezone, e.g. `2024-03-04T08:00`, unless
+// they carry an offset of their own, e.g. `2024-03-04T08:00:00+01:00`
+pub fn parse_schedule(input: &str, timezone: Tz) -> Result<DateTime<Utc>, String> {
+    if let Ok(scheduled_at) = DateTime::parse_from_rfc3339(input) {
+        return Ok(scheduled_at.with_timezone(&Utc));
+    }
+    let local = LOCAL_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+        .ok_or_else(|| format!("{} is not a valid date and time.", input))?;
+    match timezone.from_local_datetime(&local) {
+        LocalResult::Single(scheduled_at) => Ok(scheduled_at.with_timezone(&Utc)),
+        // Clocks going back repeat an hour, we go with its first occurrence
+        LocalResult::Ambiguous(earliest, _) => Ok(earliest.with_timezone(&Utc)),
+        LocalResult::None => Err(format!("{} does not exist in {}.", input, timezone)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use chrono_tz::Europe::Madrid;
+    use claims::{assert_err, assert_ok_eq};
+
+    use super::parse_schedule;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn local_times_are_read_in_the_list_timezone() {
+        assert_ok_eq!(
+            parse_schedule("2024-03-04T08:00", Madrid),
+            utc("2024-03-04T07:00:00Z")
+        );
+        assert_ok_eq!(
+            parse_schedule("2024-07-01T08:00:00", Madrid),
+            utc("2024-07-01T06:00:00Z")
+        );
+    }
+
+    #[test]
+    fn times_with_an_offset_keep_it() {
+        assert_ok_eq!(
+            parse_schedule("2024-03-04T08:00:00-05:00", Madrid),
+            utc("2024-03-04T13:00:00Z")
+        );
+    }
+
+    #[test]
+    fn times_skipped_by_a_clock_change_are_rejected() {
+        assert_err!(parse_schedule("2024-03-31T02:30", Madrid));
+    }
+
+    #[test]
+    fn repeated_times_resolve_to_their_first_occurrence() {
+        assert_ok_eq!(
+            parse_schedule("2024-10-27T02:30", Madrid),
+            utc("2024-10-27T00:30:00Z")
+        );
+    }
+
+    #[test]
+    fn malformed_times_are_rejected() {
+        assert_err!(parse_schedule("next monday", Madrid));
+        assert_err!(parse_schedule("2024-03-04", Madrid));
+    }
+}
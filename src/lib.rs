@@ -1,6 +1,19 @@
+pub mod authentication;
+pub mod clock;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_templates;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod markdown;
+pub mod newsletter_issues;
 pub mod routes;
+pub mod sent_messages;
+pub mod session_state;
+pub mod session_store;
 pub mod startup;
+pub mod suppressions;
 pub mod telemetry;
+pub mod utils;
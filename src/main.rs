@@ -1,9 +1,12 @@
+use anyhow::Context;
+use secrecy::Secret;
+use zero2prod::authentication::{create_admin, NewPassword};
 use zero2prod::configuration;
-use zero2prod::startup::Application;
+use zero2prod::startup::{connection_pool, Application};
 use zero2prod::telemetry;
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
+async fn main() -> Result<(), anyhow::Error> {
     let subscriber = telemetry::get_subscriber(
         String::from("zero2prod"),
         String::from("info"),
@@ -13,6 +16,30 @@ async fn main() -> Result<(), std::io::Error> {
 
     // Expected to return a Settings instance that contains ApplicationSettings and DatabaseSettings
     let configuration = configuration::get_configuration().expect("Failed to read configuration.");
-    let _application = Application::build(configuration).await?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [] => {
+            let application = Application::build(configuration).await?;
+            application.run().await?;
+        }
+        // `zero2prod create-admin <username>`, with the password on stdin so that it stays out
+        // of the shell history and the process list
+        [command, username] if command == "create-admin" => {
+            let mut password = String::new();
+            std::io::stdin()
+                .read_line(&mut password)
+                .context("Failed to read the password from stdin.")?;
+            let password = NewPassword::parse(Secret::new(password.trim_end().to_owned()))
+                .map_err(anyhow::Error::msg)?;
+            let user_id = create_admin(
+                username,
+                password,
+                &connection_pool(&configuration.database),
+            )
+            .await?;
+            tracing::info!(%user_id, "Created the admin {}", username);
+        }
+        _ => anyhow::bail!("Usage: zero2prod [create-admin <username>]"),
+    }
     Ok(())
 }
@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+// Email clients drop <style> blocks, every element carries its own style instead
+const INLINE_STYLES: [(&str, &str); 13] = [
+    ("p", "margin: 0 0 16px; font-size: 16px; line-height: 1.5;"),
+    (
+        "h1",
+        "margin: 24px 0 16px; font-size: 26px; line-height: 1.25;",
+    ),
+    (
+        "h2",
+        "margin: 24px 0 16px; font-size: 22px; line-height: 1.25;",
+    ),
+    (
+        "h3",
+        "margin: 24px 0 16px; font-size: 18px; line-height: 1.25;",
+    ),
+    ("a", "color: #1a73e8;"),
+    ("img", "max-width: 100%; height: auto; border: 0;"),
+    ("ul", "margin: 0 0 16px; padding-left: 24px;"),
+    ("ol", "margin: 0 0 16px; padding-left: 24px;"),
+    ("li", "margin: 0 0 4px; font-size: 16px; line-height: 1.5;"),
+    (
+        "blockquote",
+        "margin: 0 0 16px; padding-left: 16px; border-left: 4px solid #dddddd; color: #555555;",
+    ),
+    (
+        "pre",
+        "margin: 0 0 16px; padding: 12px; background-color: #f5f5f5; overflow-x: auto;",
+    ),
+    (
+        "code",
+        "font-family: Menlo, Consolas, monospace; font-size: 14px;",
+    ),
+    (
+        "hr",
+        "margin: 24px 0; border: 0; border-top: 1px solid #dddddd;",
+    ),
+];
+
+// An issue written in markdown, in the formats it is sent in
+pub struct RenderedMarkdown {
+    pub html: String,
+    pub text: String,
+}
+
+pub fn render(markdown: &str) -> RenderedMarkdown {
+    RenderedMarkdown {
+        html: render_html(markdown),
+        text: render_text(markdown),
+    }
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH
+}
+
+// Markdown allows raw html, which is sanitized along with the rest
+fn render_html(markdown: &str) -> String {
+    let mut unsafe_html = String::new();
+    pulldown_cmark::html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options()));
+
+    let mut sanitizer = ammonia::Builder::default();
+    // Uploaded images are referenced by content id
+    sanitizer.add_url_schemes(HashSet::from(["cid"]));
+    for (tag, style) in INLINE_STYLES {
+        sanitizer.set_tag_attribute_value(tag, "style", style);
+    }
+    sanitizer.clean(&unsafe_html).to_string()
+}
+
+// Reads like the markdown without its markup: links are followed by their url, lists keep
+// their bullets and raw html is left out
+fn render_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut links: Vec<String> = Vec::new();
+    // The next number of each ordered list, None for bullet lists
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    for event in Parser::new_ext(markdown, options()) {
+        match event {
+            Event::Start(Tag::List(first_number)) => {
+                if lists.is_empty() {
+                    end_block(&mut text);
+                } else {
+                    end_line(&mut text);
+                }
+                lists.push(first_number);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    end_block(&mut text);
+                }
+            }
+            Event::Start(Tag::Item) => {
+                end_line(&mut text);
+                text.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        text.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => text.push_str("- "),
+                }
+            }
+            Event::Start(Tag::Link { dest_url, .. }) => links.push(dest_url.to_string()),
+            Event::End(TagEnd::Link) => {
+                let url = links.pop().unwrap_or_default();
+                // Autolinks already show their url
+                if !text.ends_with(&url) {
+                    text.push_str(&format!(" ({})", url));
+                }
+            }
+            Event::Start(Tag::Image { .. }) => text.push('['),
+            Event::End(TagEnd::Image) => text.push(']'),
+            Event::End(
+                TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::CodeBlock | TagEnd::BlockQuote(_),
+            ) => {
+                // Paragraphs of a list item stay with the item
+                if lists.is_empty() {
+                    end_block(&mut text);
+                } else {
+                    end_line(&mut text);
+                }
+            }
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Rule => {
+                text.push_str("----");
+                end_block(&mut text);
+            }
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+fn end_line(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+fn end_block(text: &mut String) {
+    end_line(text);
+    if !text.is_empty() && !text.ends_with("\n\n") {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+
+    #[test]
+    fn markdown_is_rendered_to_styled_html() {
+        let rendered = render("# Title\n\nSome *emphasis*.");
+
+        assert_eq!(
+            rendered.html,
+            "<h1 style=\"margin: 24px 0 16px; font-size: 26px; line-height: 1.25;\">Title</h1>\n\
+            <p style=\"margin: 0 0 16px; font-size: 16px; line-height: 1.5;\">\
+            Some <em>emphasis</em>.</p>\n"
+        );
+    }
+
+    #[test]
+    fn raw_html_is_sanitized() {
+        let rendered = render(
+            "Hello<script>alert('pwned')</script> <a href=\"javascript:alert(1)\" \
+            onclick=\"alert(2)\">there</a>",
+        );
+
+        assert!(!rendered.html.contains("script"));
+        assert!(!rendered.html.contains("javascript"));
+        assert!(!rendered.html.contains("onclick"));
+        assert!(rendered.html.contains("there</a>"));
+    }
+
+    #[test]
+    fn images_can_reference_uploaded_images() {
+        let rendered = render("![Cover](cid:2c7fbf2f-34a0-4a3b-9e7b-0e1c5b6b1a1e)");
+
+        assert!(rendered
+            .html
+            .contains("src=\"cid:2c7fbf2f-34a0-4a3b-9e7b-0e1c5b6b1a1e\""));
+    }
+
+    #[test]
+    fn the_text_version_reads_like_the_markdown() {
+        let rendered = render(
+            "# Title\n\n\
+            A [link](https://example.com) and <https://example.org>.\n\n\
+            - one\n\
+            - two\n  \
+              1. nested\n\n\
+            1. first\n\
+            2. second\n\n\
+            ---\n\n\
+            ![Cover](cid:cover)",
+        );
+
+        assert_eq!(
+            rendered.text,
+            "Title\n\n\
+            A link (https://example.com) and https://example.org.\n\n\
+            - one\n\
+            - two\n  \
+              1. nested\n\n\
+            1. first\n\
+            2. second\n\n\
+            ----\n\n\
+            [Cover]"
+        );
+    }
+
+    #[test]
+    fn raw_html_is_left_out_of_the_text_version() {
+        let rendered = render("Hello <b>there</b>");
+
+        assert_eq!(rendered.text, "Hello there");
+    }
+}
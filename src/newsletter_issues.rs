@@ -0,0 +1,263 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// Raised by the database when an issue is moved in a way the workflow doesn't allow
+const INVALID_TRANSITION: &str = "NI001";
+// Raised by the database when an issue is approved by someone who edited it since its last
+// approval
+const APPROVER_EDITED_ISSUE: &str = "NI002";
+
+// Where an issue stands in the review workflow:
+// draft -> in_review -> approved -> scheduled -> sending -> sent
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueStatus {
+    Draft,
+    InReview,
+    Approved,
+    Scheduled,
+    Sending,
+    Sent,
+}
+
+impl IssueStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Draft => "draft",
+            Self::InReview => "in_review",
+            Self::Approved => "approved",
+            Self::Scheduled => "scheduled",
+            Self::Sending => "sending",
+            Self::Sent => "sent",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "draft" => Ok(Self::Draft),
+            "in_review" => Ok(Self::InReview),
+            "approved" => Ok(Self::Approved),
+            "scheduled" => Ok(Self::Scheduled),
+            "sending" => Ok(Self::Sending),
+            "sent" => Ok(Self::Sent),
+            other => Err(format!("{} is not a valid issue status.", other)),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TransitionError {
+    #[error("A newsletter issue can't go from {from} to {to}.")]
+    NotAllowed {
+        from: &'static str,
+        to: &'static str,
+    },
+    #[error(transparent)]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApprovalError {
+    #[error("An issue has to be approved by someone who didn't edit it.")]
+    ApproverEditedIssue,
+    #[error(transparent)]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+pub struct IssueState {
+    pub status: IssueStatus,
+    pub author_id: Option<Uuid>,
+    pub scheduled_at: Option<DateTime<Utc>>,
+}
+
+// The lock keeps the issue as it is until the transaction ends: the scheduler skips it and
+// concurrent changes wait
+#[tracing::instrument(name = "Locking a newsletter issue", skip(transaction))]
+pub async fn lock_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueState>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT status, author_id, scheduled_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    row.map(|r| {
+        Ok(IssueState {
+            status: IssueStatus::parse(&r.status).map_err(|e| sqlx::Error::Decode(e.into()))?,
+            author_id: r.author_id,
+            scheduled_at: r.scheduled_at,
+        })
+    })
+    .transpose()
+}
+
+// The database holds the allowed transitions and refuses any other
+#[tracing::instrument(name = "Moving a newsletter issue", skip(executor))]
+pub async fn set_status(
+    executor: impl PgExecutor<'_>,
+    newsletter_issue_id: Uuid,
+    from: IssueStatus,
+    to: IssueStatus,
+) -> Result<(), TransitionError> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        to.as_str()
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| match e.as_database_error().and_then(|e| e.code()) {
+        Some(code) if code == INVALID_TRANSITION => TransitionError::NotAllowed {
+            from: from.as_str(),
+            to: to.as_str(),
+        },
+        _ => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            TransitionError::UnexpectedError(e)
+        }
+    })?;
+    Ok(())
+}
+
+// Dropping the approval, with `None`, always works. The database refuses approvers who edited
+// the issue since it was last approved
+#[tracing::instrument(name = "Setting the approver of a newsletter issue", skip(transaction))]
+pub async fn set_approver(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    approved_by: Option<Uuid>,
+) -> Result<(), ApprovalError> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET approved_by = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        approved_by
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| match e.as_database_error().and_then(|e| e.code()) {
+        Some(code) if code == APPROVER_EDITED_ISSUE => ApprovalError::ApproverEditedIssue,
+        _ => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            ApprovalError::UnexpectedError(e)
+        }
+    })?;
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct Revision {
+    pub revision: i32,
+    pub title: String,
+    pub markdown_content: Option<String>,
+    pub text_content: String,
+    pub html_content: String,
+    pub edited_by: String,
+    pub edited_at: DateTime<Utc>,
+}
+
+// Snapshots the issue as it is now, numbered after the previous revision
+#[tracing::instrument(name = "Recording a revision of a newsletter issue", skip(transaction))]
+pub async fn record_revision(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    edited_by: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_revisions (
+            newsletter_issue_id, revision, title, markdown_content, text_content, html_content,
+            edited_by, edited_at
+        )
+        SELECT i.newsletter_issue_id,
+            COALESCE(
+                (SELECT MAX(revision) FROM newsletter_issue_revisions r
+                WHERE r.newsletter_issue_id = i.newsletter_issue_id),
+                0
+            ) + 1,
+            i.title, i.markdown_content, i.text_content, i.html_content, $2, now()
+        FROM newsletter_issues i
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        edited_by
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+// Oldest first
+#[tracing::instrument(name = "Listing the revisions of a newsletter issue", skip(pool))]
+pub async fn list_revisions(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<Revision>, sqlx::Error> {
+    sqlx::query_as!(
+        Revision,
+        r#"
+        SELECT r.revision, r.title, r.markdown_content, r.text_content, r.html_content,
+            u.username AS edited_by, r.edited_at
+        FROM newsletter_issue_revisions r
+        JOIN users u ON u.user_id = r.edited_by
+        WHERE r.newsletter_issue_id = $1
+        ORDER BY r.revision
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_err;
+
+    use super::IssueStatus;
+
+    #[test]
+    fn statuses_round_trip_through_their_database_name() {
+        for status in [
+            IssueStatus::Draft,
+            IssueStatus::InReview,
+            IssueStatus::Approved,
+            IssueStatus::Scheduled,
+            IssueStatus::Sending,
+            IssueStatus::Sent,
+        ] {
+            assert_eq!(IssueStatus::parse(status.as_str()), Ok(status));
+        }
+    }
+
+    #[test]
+    fn unknown_statuses_are_rejected() {
+        assert_err!(IssueStatus::parse("published"));
+    }
+}
@@ -0,0 +1,18 @@
+mod dashboard;
+mod logout;
+mod newsletters;
+mod password;
+mod suppressions;
+
+pub use dashboard::admin_dashboard;
+pub use logout::log_out;
+pub use newsletters::{
+    approve_newsletter, cancel_scheduled_newsletter, edit_newsletter, list_newsletter_revisions,
+    preview_newsletter, reopen_newsletter, reschedule_newsletter, send_newsletter,
+    send_test_newsletter, submit_newsletter,
+};
+pub use password::{change_password, change_password_form};
+pub use suppressions::{
+    add_suppression, add_suppression_api, list_suppressions_api, remove_suppression,
+    remove_suppression_api, suppressions_page,
+};
@@ -0,0 +1,13 @@
+use actix_web::HttpResponse;
+use actix_web_flash_messages::FlashMessage;
+
+use crate::session_state::TypedSession;
+use crate::utils::see_other;
+
+#[tracing::instrument(name = "Logging out", skip(session))]
+pub async fn log_out(session: TypedSession) -> HttpResponse {
+    // Removes the session from the store as well as the cookie from the browser
+    session.log_out();
+    FlashMessage::info("You have successfully logged out.").send();
+    see_other("/login")
+}
@@ -0,0 +1,131 @@
+mod edit;
+mod preview;
+mod schedule;
+mod test_send;
+mod workflow;
+
+pub use edit::{edit_newsletter, list_newsletter_revisions};
+pub use preview::preview_newsletter;
+pub use schedule::{cancel_scheduled_newsletter, reschedule_newsletter, send_newsletter};
+pub use test_send::send_test_newsletter;
+pub use workflow::{approve_newsletter, reopen_newsletter, submit_newsletter};
+
+use actix_web::HttpResponse;
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::email_templates::{EmailTemplates, RenderedEmail};
+use crate::issue_delivery_worker::{get_issue, Issue};
+use crate::newsletter_issues::{lock_issue, IssueState, IssueStatus, TransitionError};
+use crate::routes::subscriptions_unsubscribe::unsubscribe_link;
+
+// Greets the reader when the issue isn't rendered for anyone in particular: "Hi there,"
+const SAMPLE_NAME: &str = "there";
+
+#[derive(serde::Deserialize)]
+pub struct Personalisation {
+    // The subscriber the issue is rendered for
+    subscriber_id: Option<Uuid>,
+}
+
+// The issue as the given subscriber gets it, bar the unsubscribe link. The error is the
+// response to send back
+async fn render_issue(
+    pool: &PgPool,
+    email_templates: &EmailTemplates,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Option<Uuid>,
+) -> Result<(Issue, RenderedEmail), HttpResponse> {
+    let issue = match get_issue(pool, newsletter_issue_id).await {
+        Ok(issue) => issue,
+        Err(sqlx::Error::RowNotFound) => {
+            return Err(HttpResponse::NotFound().body("There is no such newsletter issue."))
+        }
+        Err(e) => {
+            tracing::error!("Failed to fetch the newsletter issue: {:?}", e);
+            return Err(HttpResponse::InternalServerError().finish());
+        }
+    };
+    let name = match subscriber_id {
+        Some(subscriber_id) => match get_subscriber_name(pool, subscriber_id).await {
+            Ok(Some(name)) => name,
+            Ok(None) => return Err(HttpResponse::NotFound().body("There is no such subscriber.")),
+            Err(_) => return Err(HttpResponse::InternalServerError().finish()),
+        },
+        None => String::from(SAMPLE_NAME),
+    };
+    // Only the name is personalised: previews and tests are seen by staff, who must not get a
+    // working link for the subscriber. The link is signed for `Uuid::nil()` instead, which no
+    // subscriber has. Following it answers as if unsubscribed but changes nothing
+    let unsubscribe_link = unsubscribe_link(base_url, Uuid::nil(), hmac_secret);
+    let body = email_templates.newsletter(
+        &name,
+        &issue.title,
+        &issue.html_content,
+        &issue.text_content,
+        &unsubscribe_link,
+    );
+    Ok((issue, body))
+}
+
+#[tracing::instrument(name = "Getting the subscriber name", skip(pool))]
+async fn get_subscriber_name(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT name FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(row.map(|r| r.name))
+}
+
+// Starts the transaction a change to an issue is made in, with the issue locked. The error is
+// the response to send back
+async fn lock_for_change(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<(Transaction<'static, Postgres>, IssueState), HttpResponse> {
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return Err(HttpResponse::InternalServerError().finish()),
+    };
+    match lock_issue(&mut transaction, newsletter_issue_id).await {
+        Ok(Some(issue)) => Ok((transaction, issue)),
+        Ok(None) => Err(HttpResponse::NotFound().finish()),
+        Err(_) => Err(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+fn transition_error(e: TransitionError) -> HttpResponse {
+    match e {
+        TransitionError::NotAllowed { .. } => {
+            HttpResponse::Conflict().json(serde_json::json!({ "error": e.to_string() }))
+        }
+        TransitionError::UnexpectedError(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Answers with where the issue stands once the change is committed
+async fn commit_change(
+    transaction: Transaction<'static, Postgres>,
+    newsletter_issue_id: Uuid,
+    status: IssueStatus,
+) -> HttpResponse {
+    match transaction.commit().await {
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "status": status,
+        })),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
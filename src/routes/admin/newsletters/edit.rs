@@ -0,0 +1,129 @@
+use actix_web::{web, HttpResponse};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::newsletter_issues::{list_revisions, record_revision, IssueStatus};
+use crate::routes::admin::newsletters::lock_for_change;
+use crate::routes::newsletters::{attach_images, check_images, Content, IssueContent};
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+    #[serde(default)]
+    images: Vec<Uuid>,
+}
+
+// Replaces the issue with a new version, kept as a revision. Only drafts can be edited:
+// issues under review or approved are reopened first
+#[tracing::instrument(name = "Editing a newsletter issue", skip(body, pool, user_id))]
+pub async fn edit_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> HttpResponse {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let content = body.content.render();
+    let images = match check_images(&pool, &body.images, &content).await {
+        Ok(images) => images,
+        Err(response) => return response,
+    };
+
+    let (mut transaction, issue) = match lock_for_change(&pool, newsletter_issue_id).await {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+    if issue.status != IssueStatus::Draft {
+        return HttpResponse::Conflict()
+            .json(serde_json::json!({ "error": "Only drafts can be edited." }));
+    }
+    if update_issue(&mut transaction, newsletter_issue_id, &body.title, &content)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if detach_images(&mut transaction, newsletter_issue_id)
+        .await
+        .is_err()
+        || attach_images(&mut transaction, newsletter_issue_id, &images)
+            .await
+            .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if record_revision(&mut transaction, newsletter_issue_id, **user_id)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    match transaction.commit().await {
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "status": IssueStatus::Draft,
+        })),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Every version of the issue, oldest first
+#[tracing::instrument(name = "Listing the revisions of a newsletter issue", skip(pool))]
+pub async fn list_newsletter_revisions(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    // Issues are created with their first revision, none means there is no such issue
+    match list_revisions(&pool, newsletter_issue_id.into_inner()).await {
+        Ok(revisions) if revisions.is_empty() => HttpResponse::NotFound().finish(),
+        Ok(revisions) => HttpResponse::Ok().json(revisions),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "Updating the newsletter issue in the database", skip_all)]
+async fn update_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    title: &str,
+    content: &IssueContent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, markdown_content = $3, text_content = $4, html_content = $5
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        title,
+        content.markdown,
+        content.text,
+        content.html
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+async fn detach_images(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM newsletter_issue_images WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use base64::Engine;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::email_templates::EmailTemplates;
+use crate::issue_delivery_worker::get_issue_images;
+use crate::routes::admin::newsletters::render_issue;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+
+#[derive(serde::Deserialize)]
+pub struct QueryParams {
+    subscriber_id: Option<Uuid>,
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Html,
+    Text,
+}
+
+// The body of the email the subscriber would get, or a sample reader when none is given
+#[tracing::instrument(
+    name = "Previewing a newsletter issue",
+    skip(query, pool, email_templates, base_url, hmac_secret),
+    fields(subscriber_id = ?query.subscriber_id)
+)]
+pub async fn preview_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    query: web::Query<QueryParams>,
+    pool: web::Data<PgPool>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let body = match render_issue(
+        &pool,
+        &email_templates,
+        &base_url.0,
+        &hmac_secret.0,
+        newsletter_issue_id,
+        query.subscriber_id,
+    )
+    .await
+    {
+        Ok((_, body)) => body,
+        Err(response) => return response,
+    };
+
+    match query.format {
+        Format::Text => HttpResponse::Ok()
+            .content_type(ContentType::plaintext())
+            .body(body.text),
+        Format::Html => {
+            let images = match get_issue_images(&pool, newsletter_issue_id).await {
+                Ok(images) => images,
+                Err(e) => {
+                    tracing::error!("Failed to fetch the issue images: {:?}", e);
+                    return HttpResponse::InternalServerError().finish();
+                }
+            };
+            // Browsers don't know about the attachments of an email, the images are inlined
+            let mut html = body.html;
+            for image in images {
+                if let Some(content_id) = &image.content_id {
+                    let data_url = format!(
+                        "data:{};base64,{}",
+                        image.content_type,
+                        base64::engine::general_purpose::STANDARD.encode(&image.content)
+                    );
+                    html = html.replace(&format!("cid:{}", content_id), &data_url);
+                }
+            }
+            HttpResponse::Ok()
+                .content_type(ContentType::html())
+                .body(html)
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::clock::Clock;
+use crate::issue_scheduler::{parse_schedule, start_sending};
+use crate::newsletter_issues::{set_status, IssueStatus};
+use crate::routes::admin::newsletters::{commit_change, lock_for_change, transition_error};
+use crate::startup::ListTimezone;
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    // In the list's timezone unless it carries an offset
+    scheduled_at: String,
+}
+
+// Schedules an approved issue, or moves the publication of a scheduled one
+#[tracing::instrument(
+    name = "Rescheduling a newsletter issue",
+    skip(body, pool, timezone, clock)
+)]
+pub async fn reschedule_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    timezone: web::Data<ListTimezone>,
+    clock: web::Data<dyn Clock>,
+) -> HttpResponse {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let scheduled_at = match parse_schedule(&body.scheduled_at, timezone.0) {
+        Ok(scheduled_at) => scheduled_at,
+        Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": e })),
+    };
+    if scheduled_at <= clock.now() {
+        return HttpResponse::BadRequest()
+            .json(serde_json::json!({ "error": "Issues can only be scheduled in the future." }));
+    }
+
+    let (mut transaction, issue) = match lock_for_change(&pool, newsletter_issue_id).await {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+    if set_schedule(&mut transaction, newsletter_issue_id, Some(scheduled_at))
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if issue.status != IssueStatus::Scheduled {
+        if let Err(e) = set_status(
+            &mut *transaction,
+            newsletter_issue_id,
+            issue.status,
+            IssueStatus::Scheduled,
+        )
+        .await
+        {
+            return transition_error(e);
+        }
+    }
+    commit_change(transaction, newsletter_issue_id, IssueStatus::Scheduled).await
+}
+
+// The issue goes back to approved, until it is scheduled again
+#[tracing::instrument(name = "Cancelling a scheduled newsletter issue", skip(pool))]
+pub async fn cancel_scheduled_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let (mut transaction, issue) = match lock_for_change(&pool, newsletter_issue_id).await {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+    let error = match issue.status {
+        IssueStatus::Scheduled => None,
+        IssueStatus::Sending | IssueStatus::Sent => Some("The issue is already published."),
+        _ => Some("The issue is not scheduled."),
+    };
+    if let Some(error) = error {
+        return HttpResponse::Conflict().json(serde_json::json!({ "error": error }));
+    }
+    if set_schedule(&mut transaction, newsletter_issue_id, None)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if let Err(e) = set_status(
+        &mut *transaction,
+        newsletter_issue_id,
+        issue.status,
+        IssueStatus::Approved,
+    )
+    .await
+    {
+        return transition_error(e);
+    }
+    match transaction.commit().await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Sends an approved issue right away: it is scheduled for now and published at once
+#[tracing::instrument(name = "Sending a newsletter issue", skip(pool, clock))]
+pub async fn send_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    clock: web::Data<dyn Clock>,
+) -> HttpResponse {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let now = clock.now();
+    let (mut transaction, issue) = match lock_for_change(&pool, newsletter_issue_id).await {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+    if set_schedule(&mut transaction, newsletter_issue_id, Some(now))
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if let Err(e) = set_status(
+        &mut *transaction,
+        newsletter_issue_id,
+        issue.status,
+        IssueStatus::Scheduled,
+    )
+    .await
+    {
+        return transition_error(e);
+    }
+    if start_sending(&mut transaction, newsletter_issue_id, now)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    commit_change(transaction, newsletter_issue_id, IssueStatus::Sending).await
+}
+
+async fn set_schedule(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    scheduled_at: Option<DateTime<Utc>>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_at = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        scheduled_at
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
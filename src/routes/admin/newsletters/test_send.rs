@@ -0,0 +1,96 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::email_client::{EmailClient, EmailOptions, MessageStream};
+use crate::email_templates::EmailTemplates;
+use crate::issue_delivery_worker::get_issue_images;
+use crate::routes::admin::newsletters::{render_issue, Personalisation};
+use crate::sent_messages::record_sent_message;
+use crate::startup::{ApplicationBaseUrl, HmacSecret, StaffEmails};
+
+// Sends the issue to the staff addresses only. Nothing is queued and no subscriber gets
+// anything, they are at most the ones the issue is personalised for
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Sending a test of a newsletter issue",
+    skip(query, pool, email_client, email_templates, base_url, hmac_secret, staff_emails),
+    fields(subscriber_id = ?query.subscriber_id)
+)]
+pub async fn send_test_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    query: web::Query<Personalisation>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    staff_emails: web::Data<StaffEmails>,
+) -> HttpResponse {
+    if staff_emails.0.is_empty() {
+        return HttpResponse::Conflict().body("There are no staff addresses to send tests to.");
+    }
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let (issue, body) = match render_issue(
+        &pool,
+        &email_templates,
+        &base_url.0,
+        &hmac_secret.0,
+        newsletter_issue_id,
+        query.subscriber_id,
+    )
+    .await
+    {
+        Ok(rendered) => rendered,
+        Err(response) => return response,
+    };
+    let images = match get_issue_images(&pool, newsletter_issue_id).await {
+        Ok(images) => images,
+        Err(e) => {
+            tracing::error!("Failed to fetch the issue images: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let subject = format!("[Test] {}", issue.title);
+    let options = EmailOptions {
+        message_stream: MessageStream::Broadcast,
+        tag: Some("newsletter-test"),
+        metadata: [("newsletter_issue_id", newsletter_issue_id.to_string())].into(),
+        attachments: &images,
+        ..Default::default()
+    };
+    let mut failed = false;
+    for recipient in &staff_emails.0 {
+        match email_client
+            .send_email_with_options(
+                recipient.clone(),
+                &subject,
+                &body.html,
+                &body.text,
+                None,
+                &options,
+            )
+            .await
+        {
+            // Not tied to the issue, its delivery reports only cover the subscribers
+            Ok(sent) => {
+                if let Err(e) =
+                    record_sent_message(&**pool, recipient.as_ref(), None, None, &sent).await
+                {
+                    tracing::error!("Failed to record a test send: {:?}", e);
+                }
+            }
+            Err(e) => {
+                tracing::error!("Failed to send a test of the issue: {:?}", e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        return HttpResponse::InternalServerError().finish();
+    }
+    let recipients: Vec<&str> = staff_emails.0.iter().map(|e| e.as_ref()).collect();
+    HttpResponse::Ok().json(serde_json::json!({ "recipients": recipients }))
+}
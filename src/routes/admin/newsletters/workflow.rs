@@ -0,0 +1,89 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::newsletter_issues::{set_approver, set_status, ApprovalError, IssueStatus};
+use crate::routes::admin::newsletters::{commit_change, lock_for_change, transition_error};
+
+// A draft is ready to be reviewed
+#[tracing::instrument(name = "Submitting a newsletter issue for review", skip(pool))]
+pub async fn submit_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    move_issue(
+        &pool,
+        newsletter_issue_id.into_inner(),
+        IssueStatus::InReview,
+    )
+    .await
+}
+
+// Issues need the approval of an admin other than their author before they can be sent. Nor
+// can it come from anyone who edited the issue since it was last approved
+#[tracing::instrument(name = "Approving a newsletter issue", skip(pool, user_id))]
+pub async fn approve_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> HttpResponse {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let (mut transaction, issue) = match lock_for_change(&pool, newsletter_issue_id).await {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+    if issue.author_id == Some(**user_id) {
+        return HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "An issue has to be approved by someone other than its author."
+        }));
+    }
+    if let Err(e) = set_status(
+        &mut *transaction,
+        newsletter_issue_id,
+        issue.status,
+        IssueStatus::Approved,
+    )
+    .await
+    {
+        return transition_error(e);
+    }
+    match set_approver(&mut transaction, newsletter_issue_id, Some(**user_id)).await {
+        Ok(()) => {}
+        Err(e @ ApprovalError::ApproverEditedIssue) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({ "error": e.to_string() }))
+        }
+        Err(ApprovalError::UnexpectedError(_)) => {
+            return HttpResponse::InternalServerError().finish()
+        }
+    }
+    commit_change(transaction, newsletter_issue_id, IssueStatus::Approved).await
+}
+
+// Back to draft, for changes asked by the reviewer or needed after the approval. Any
+// approval is dropped, the new version needs one of its own
+#[tracing::instrument(name = "Reopening a newsletter issue", skip(pool))]
+pub async fn reopen_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    move_issue(&pool, newsletter_issue_id.into_inner(), IssueStatus::Draft).await
+}
+
+async fn move_issue(pool: &PgPool, newsletter_issue_id: Uuid, to: IssueStatus) -> HttpResponse {
+    let (mut transaction, issue) = match lock_for_change(pool, newsletter_issue_id).await {
+        Ok(locked) => locked,
+        Err(response) => return response,
+    };
+    if let Err(e) = set_status(&mut *transaction, newsletter_issue_id, issue.status, to).await {
+        return transition_error(e);
+    }
+    if to == IssueStatus::Draft
+        && set_approver(&mut transaction, newsletter_issue_id, None)
+            .await
+            .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    commit_change(transaction, newsletter_issue_id, to).await
+}
@@ -0,0 +1,86 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::authentication::{
+    self, validate_credentials, AuthError, Credentials, NewPassword, UserId,
+};
+use crate::routes::admin::dashboard::get_username;
+use crate::session_state::TypedSession;
+use crate::session_store::AppSessionStore;
+use crate::utils::see_other;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    current_password: Secret<String>,
+    new_password: Secret<String>,
+    new_password_check: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Changing the password of an admin",
+    skip(user_id, form, pool, session, session_store)
+)]
+pub async fn change_password(
+    user_id: web::ReqData<UserId>,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    session_store: web::Data<AppSessionStore>,
+) -> HttpResponse {
+    let user_id = **user_id;
+
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        return password_change_failed(
+            "You entered two different new passwords - the field values must match.",
+        );
+    }
+
+    let username = match get_username(user_id, &pool).await {
+        Ok(username) => username,
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let credentials = Credentials {
+        username,
+        password: form.0.current_password,
+    };
+    match validate_credentials(credentials, &pool).await {
+        Ok(_) => {}
+        Err(AuthError::InvalidCredentials(_)) => {
+            return password_change_failed("The current password is incorrect.");
+        }
+        Err(AuthError::UnexpectedError(e)) => {
+            tracing::error!("Failed to validate credentials: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let new_password = match NewPassword::parse(form.0.new_password) {
+        Ok(new_password) => new_password,
+        Err(e) => return password_change_failed(&e),
+    };
+    if let Err(e) = authentication::change_password(user_id, new_password, &pool).await {
+        tracing::error!("{:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    // Anybody logged in with the old password is kicked out. The current session is
+    // dropped as well, renewing it saves its state under a brand new key
+    if let Err(e) = session_store.delete_user_sessions(user_id).await {
+        tracing::error!("{:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    session.renew();
+
+    FlashMessage::info("Your password has been changed.").send();
+    see_other("/admin/password")
+}
+
+fn password_change_failed(message: &str) -> HttpResponse {
+    FlashMessage::error(message).send();
+    see_other("/admin/password")
+}
@@ -0,0 +1,21 @@
+mod api;
+mod get;
+mod post;
+
+pub use api::{add_suppression_api, list_suppressions_api, remove_suppression_api};
+pub use get::suppressions_page;
+pub use post::{add_suppression, remove_suppression};
+
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::routes::admin::dashboard::get_username;
+
+// Recorded as the source of the suppressions added by an admin
+async fn admin_source(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow::Error> {
+    let username = get_username(user_id, pool)
+        .await
+        .context("Failed to identify the admin")?;
+    Ok(format!("admin:{}", username))
+}
@@ -0,0 +1,65 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::authentication::UserId;
+use crate::domain::SubscriberEmail;
+use crate::routes::admin::suppressions::admin_source;
+use crate::suppressions::{self, list_suppressions, suppress_email, SuppressionReason};
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    email: String,
+}
+
+#[tracing::instrument(name = "Listing suppressions through the API", skip(pool))]
+pub async fn list_suppressions_api(pool: web::Data<PgPool>) -> HttpResponse {
+    match list_suppressions(&pool).await {
+        Ok(suppressions) => HttpResponse::Ok().json(suppressions),
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// 201 when the address is added, 409 when it was already suppressed
+#[tracing::instrument(
+    name = "Adding a suppression through the API",
+    skip(body, pool, user_id)
+)]
+pub async fn add_suppression_api(
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> HttpResponse {
+    let email = match SubscriberEmail::parse(body.0.email) {
+        Ok(email) => email,
+        Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": e })),
+    };
+    let source = match admin_source(**user_id, &pool).await {
+        Ok(source) => source,
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    match suppress_email(&**pool, email.as_ref(), SuppressionReason::Manual, &source).await {
+        Ok(true) => HttpResponse::Created().finish(),
+        Ok(false) => HttpResponse::Conflict().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// 204 when the address is removed, 404 when it wasn't suppressed
+#[tracing::instrument(name = "Removing a suppression through the API", skip(pool))]
+pub async fn remove_suppression_api(
+    email: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match suppressions::remove_suppression(&pool, &email).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
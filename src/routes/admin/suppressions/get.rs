@@ -0,0 +1,87 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::suppressions::list_suppressions;
+
+#[tracing::instrument(name = "Showing the suppression list", skip(pool, flash_messages))]
+pub async fn suppressions_page(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> HttpResponse {
+    let suppressions = match list_suppressions(&pool).await {
+        Ok(suppressions) => suppressions,
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let mut messages_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            messages_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let mut rows_html = String::new();
+    for suppression in &suppressions {
+        let email = htmlescape::encode_minimal(&suppression.email);
+        writeln!(
+            rows_html,
+            r#"        <tr>
+            <td>{email}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>
+                <form action="/admin/suppressions/remove" method="post">
+                    <input type="hidden" name="email" value="{}">
+                    <button type="submit">Remove</button>
+                </form>
+            </td>
+        </tr>"#,
+            suppression.reason.as_str(),
+            htmlescape::encode_minimal(&suppression.source),
+            suppression.created_at.format("%Y-%m-%d %H:%M"),
+            htmlescape::encode_attribute(&suppression.email),
+        )
+        .unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Suppression list</title>
+</head>
+<body>
+    {messages_html}
+    <p>These addresses can't subscribe and never receive our emails.</p>
+    <form action="/admin/suppressions" method="post">
+        <label>Email
+            <input type="email" placeholder="Enter the address to block" name="email">
+        </label>
+        <button type="submit">Suppress</button>
+    </form>
+    <table>
+        <tr>
+            <th>Email</th>
+            <th>Reason</th>
+            <th>Source</th>
+            <th>Since</th>
+            <th></th>
+        </tr>
+{rows_html}    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        ))
+}
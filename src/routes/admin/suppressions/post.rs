@@ -0,0 +1,64 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use crate::authentication::UserId;
+use crate::domain::SubscriberEmail;
+use crate::routes::admin::suppressions::admin_source;
+use crate::suppressions::{self, suppress_email, SuppressionReason};
+use crate::utils::see_other;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+}
+
+#[tracing::instrument(name = "Adding a suppression", skip(form, pool, user_id))]
+pub async fn add_suppression(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> HttpResponse {
+    let email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return see_other("/admin/suppressions");
+        }
+    };
+    let source = match admin_source(**user_id, &pool).await {
+        Ok(source) => source,
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    match suppress_email(&**pool, email.as_ref(), SuppressionReason::Manual, &source).await {
+        Ok(true) => {
+            FlashMessage::info(format!("{} has been suppressed.", email.as_ref())).send();
+        }
+        Ok(false) => {
+            FlashMessage::info(format!("{} was already suppressed.", email.as_ref())).send();
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    see_other("/admin/suppressions")
+}
+
+#[tracing::instrument(name = "Removing a suppression", skip(form, pool))]
+pub async fn remove_suppression(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match suppressions::remove_suppression(&pool, &form.email).await {
+        Ok(true) => {
+            FlashMessage::info(format!("{} is no longer suppressed.", form.email)).send();
+        }
+        Ok(false) => {
+            FlashMessage::error(format!("{} was not suppressed.", form.email)).send();
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+    see_other("/admin/suppressions")
+}
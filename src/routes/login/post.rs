@@ -0,0 +1,58 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::Secret;
+use sqlx::PgPool;
+
+use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::session_state::TypedSession;
+use crate::utils::see_other;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    username: String,
+    password: Secret<String>,
+}
+
+#[tracing::instrument(
+    name = "Logging in",
+    skip(form, pool, session),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn login(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+) -> HttpResponse {
+    let credentials = Credentials {
+        username: form.0.username,
+        password: form.0.password,
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+
+    match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => {
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+            // Whatever session key the client had before logging in is discarded
+            session.renew();
+            if let Err(e) = session.insert_user_id(user_id) {
+                tracing::error!("Failed to store the user id in the session: {:?}", e);
+                return login_failed("Something went wrong. Please, try again.");
+            }
+            see_other("/admin/dashboard")
+        }
+        Err(AuthError::InvalidCredentials(e)) => {
+            tracing::warn!("Rejected a login attempt: {:?}", e);
+            login_failed("Authentication failed.")
+        }
+        Err(AuthError::UnexpectedError(e)) => {
+            tracing::error!("Failed to validate credentials: {:?}", e);
+            login_failed("Something went wrong. Please, try again.")
+        }
+    }
+}
+
+// Back to the login form, which shows the error only once
+fn login_failed(message: &str) -> HttpResponse {
+    FlashMessage::error(message).send();
+    see_other("/login")
+}
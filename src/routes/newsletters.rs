@@ -0,0 +1,279 @@
+mod images;
+
+pub use images::upload_newsletter_image;
+
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::{basic_authentication, validate_credentials, AuthError};
+use crate::email_client::{message_size, MAX_MESSAGE_SIZE};
+use crate::idempotency::{get_idempotency_key, save_response, try_processing, NextAction};
+use crate::markdown;
+use crate::newsletter_issues::record_revision;
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+    // Uploaded images embedded in the html as `cid:<image_id>`
+    #[serde(default)]
+    images: Vec<Uuid>,
+    // No longer accepted, issues are scheduled once approved. Rejected rather than ignored
+    #[serde(default)]
+    scheduled_at: Option<serde_json::Value>,
+}
+
+// Issues are written either in markdown or directly in both formats
+#[derive(serde::Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Content {
+    Markdown { markdown: String },
+    Rendered { html: String, text: String },
+}
+
+// What is stored in the issue record
+pub struct IssueContent {
+    // Kept so that issues written in markdown can be edited as such
+    pub markdown: Option<String>,
+    pub html: String,
+    pub text: String,
+}
+
+impl Content {
+    pub fn render(&self) -> IssueContent {
+        match self {
+            Self::Markdown { markdown } => {
+                let rendered = markdown::render(markdown);
+                IssueContent {
+                    markdown: Some(markdown.clone()),
+                    html: rendered.html,
+                    text: rendered.text,
+                }
+            }
+            Self::Rendered { html, text } => IssueContent {
+                markdown: None,
+                html: html.clone(),
+                text: text.clone(),
+            },
+        }
+    }
+}
+
+// Issues start as drafts of whoever created them. They go out once they are reviewed and
+// another admin approved them
+#[tracing::instrument(
+    name = "Creating a newsletter issue",
+    skip(request, body, pool),
+    fields(title = %body.title, username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn create_newsletter(
+    request: HttpRequest,
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let user_id = match authenticate(&request, &pool).await {
+        Ok(user_id) => user_id,
+        Err(response) => return response,
+    };
+    if body.scheduled_at.is_some() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Issues are scheduled once approved, not when they are created."
+        }));
+    }
+
+    let content = body.content.render();
+    let images = match check_images(&pool, &body.images, &content).await {
+        Ok(images) => images,
+        Err(response) => return response,
+    };
+
+    let idempotency_key = match get_idempotency_key(&request) {
+        Ok(idempotency_key) => idempotency_key,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => match try_processing(&pool, idempotency_key, user_id).await {
+            Ok(NextAction::StartProcessing(transaction)) => transaction,
+            Ok(NextAction::ReturnSavedResponse(saved_response)) => return saved_response,
+            Err(e) => {
+                tracing::error!("Failed to process the idempotency key: {:?}", e);
+                return HttpResponse::InternalServerError().finish();
+            }
+        },
+        None => match pool.begin().await {
+            Ok(transaction) => transaction,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+    };
+    let issue_id =
+        match insert_newsletter_issue(&mut transaction, &body.title, &content, user_id).await {
+            Ok(issue_id) => issue_id,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    if attach_images(&mut transaction, issue_id, &images)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if record_revision(&mut transaction, issue_id, user_id)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    // The id is what the issue is reviewed, edited and scheduled by
+    let response =
+        HttpResponse::Accepted().json(serde_json::json!({ "newsletter_issue_id": issue_id }));
+    match idempotency_key {
+        Some(idempotency_key) => save_response(transaction, &idempotency_key, user_id, response)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("Failed to save the response: {:?}", e);
+                HttpResponse::InternalServerError().finish()
+            }),
+        None => match transaction.commit().await {
+            Ok(_) => response,
+            Err(_) => HttpResponse::InternalServerError().finish(),
+        },
+    }
+}
+
+// The images an issue is sent with, without duplicates. The error is the response to send
+// back: 400 for unknown images, 413 when the email would be too large
+pub async fn check_images(
+    pool: &PgPool,
+    images: &[Uuid],
+    content: &IssueContent,
+) -> Result<Vec<Uuid>, HttpResponse> {
+    let mut images = images.to_vec();
+    images.sort();
+    images.dedup();
+    let image_sizes = match get_image_sizes(pool, &images).await {
+        Ok(image_sizes) => image_sizes,
+        Err(_) => return Err(HttpResponse::InternalServerError().finish()),
+    };
+    if image_sizes.len() != images.len() {
+        return Err(HttpResponse::BadRequest().body("The issue references unknown images."));
+    }
+    // Every subscriber would get the same refusal from the provider
+    if message_size(&content.html, &content.text, image_sizes) > MAX_MESSAGE_SIZE {
+        return Err(HttpResponse::PayloadTooLarge().finish());
+    }
+    Ok(images)
+}
+
+// Identifies the publisher from their HTTP Basic credentials, recording who they are on the
+// current span. The error is the response to send back
+async fn authenticate(request: &HttpRequest, pool: &PgPool) -> Result<Uuid, HttpResponse> {
+    let credentials = match basic_authentication(request.headers()) {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            tracing::warn!("Rejected a request without valid credentials: {:?}", e);
+            return Err(unauthorized());
+        }
+    };
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
+    let user_id = match validate_credentials(credentials, pool).await {
+        Ok(user_id) => user_id,
+        Err(AuthError::InvalidCredentials(e)) => {
+            tracing::warn!("Rejected a request with invalid credentials: {:?}", e);
+            return Err(unauthorized());
+        }
+        Err(AuthError::UnexpectedError(e)) => {
+            tracing::error!("Failed to validate credentials: {:?}", e);
+            return Err(HttpResponse::InternalServerError().finish());
+        }
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(user_id));
+    Ok(user_id)
+}
+
+// Asks the client to retry with HTTP Basic credentials
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static(r#"Basic realm="publish""#),
+        ))
+        .finish()
+}
+
+#[tracing::instrument(name = "Saving the newsletter issue in the database", skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    content: &IssueContent,
+    author_id: Uuid,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, markdown_content, text_content, html_content, status,
+            author_id
+        )
+        VALUES ($1, $2, $3, $4, $5, 'draft', $6)
+        "#,
+        newsletter_issue_id,
+        title,
+        content.markdown,
+        content.text,
+        content.html,
+        author_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(newsletter_issue_id)
+}
+
+// Sizes of the images that exist among the given ones
+#[tracing::instrument(name = "Looking up the issue images", skip(pool))]
+async fn get_image_sizes(pool: &PgPool, image_ids: &[Uuid]) -> Result<Vec<usize>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT octet_length(content) AS "size!"
+        FROM newsletter_images
+        WHERE image_id = ANY($1)
+        "#,
+        image_ids
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(rows.into_iter().map(|r| r.size as usize).collect())
+}
+
+#[tracing::instrument(name = "Attaching the images to the newsletter issue", skip_all)]
+pub async fn attach_images(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    image_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_images (newsletter_issue_id, image_id)
+        SELECT $1, * FROM UNNEST($2::uuid[])
+        "#,
+        newsletter_issue_id,
+        image_ids
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
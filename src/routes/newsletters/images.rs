@@ -0,0 +1,90 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use base64::Engine;
+use chrono::Utc;
+use lettre::message::header::ContentType;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::email_client::{message_size, MAX_MESSAGE_SIZE};
+use crate::routes::newsletters::authenticate;
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    name: String,
+    content_type: String,
+    // Base64 encoded
+    content: String,
+}
+
+#[derive(serde::Serialize)]
+struct UploadedImage {
+    image_id: Uuid,
+    // What the issue's html uses as the image source, e.g. <img src="cid:...">
+    src: String,
+}
+
+// Issues list the ids of the images they embed, which are sent along with every email
+#[tracing::instrument(
+    name = "Uploading a newsletter image",
+    skip(request, body, pool),
+    fields(name = %body.name, username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn upload_newsletter_image(
+    request: HttpRequest,
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate(&request, &pool).await {
+        return response;
+    }
+
+    if body.name.trim().is_empty() {
+        return HttpResponse::BadRequest().body("The image needs a name.");
+    }
+    if !body.content_type.starts_with("image/") || ContentType::parse(&body.content_type).is_err() {
+        return HttpResponse::BadRequest().body("The content type must be an image type.");
+    }
+    let content = match base64::engine::general_purpose::STANDARD.decode(&body.content) {
+        Ok(content) => content,
+        Err(_) => return HttpResponse::BadRequest().body("The content must be base64 encoded."),
+    };
+    // On its own the image already makes for an email the provider would refuse
+    if message_size("", "", [content.len()]) > MAX_MESSAGE_SIZE {
+        return HttpResponse::PayloadTooLarge().finish();
+    }
+
+    match insert_image(&pool, &body, content).await {
+        Ok(image_id) => HttpResponse::Created().json(UploadedImage {
+            image_id,
+            src: format!("cid:{}", image_id),
+        }),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[tracing::instrument(name = "Saving the newsletter image in the database", skip_all)]
+async fn insert_image(
+    pool: &PgPool,
+    body: &BodyData,
+    content: Vec<u8>,
+) -> Result<Uuid, sqlx::Error> {
+    let image_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_images (image_id, file_name, content_type, content, uploaded_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        image_id,
+        body.name,
+        body.content_type,
+        content,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(image_id)
+}
@@ -1,9 +1,18 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 use chrono::Utc;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionToken};
+use crate::email_client::{EmailClient, EmailError, EmailOptions, MessageStream, SentMessage};
+use crate::email_templates::EmailTemplates;
+use crate::idempotency::{
+    discard_saved_response, get_idempotency_key, save_response, try_processing, NextAction,
+    ANONYMOUS_USER_ID,
+};
+use crate::sent_messages::record_sent_message;
+use crate::startup::ApplicationBaseUrl;
+use crate::suppressions::is_suppressed;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
@@ -23,40 +32,222 @@ impl TryFrom<FormData> for NewSubscriber {
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool),
+    skip(request, form, pool, email_client, email_templates, base_url),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
         )
 )]
-pub async fn subscribe(form: web::Form<FormData>, pool: web::Data<PgPool>) -> HttpResponse {
+pub async fn subscribe(
+    request: HttpRequest,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    email_templates: web::Data<EmailTemplates>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
     // form.0 access underlying FormData
     let new_subscriber = match NewSubscriber::try_from(form.0) {
         Ok(subscriber) => subscriber,
         Err(_) => return HttpResponse::BadRequest().finish(),
     };
-    match insert_subscriber(&new_subscriber, &pool).await {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    // Keys sent without a logged-in user are only good for the subscription they came with
+    let idempotency_key = match get_idempotency_key(&request) {
+        Ok(idempotency_key) => idempotency_key.map(|key| {
+            key.fingerprinted(
+                "/subscriptions",
+                &[new_subscriber.email.as_ref(), new_subscriber.name.as_ref()],
+            )
+        }),
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    // Answered like any other subscription, so the form doesn't tell who is on the list
+    match is_suppressed(&**pool, &new_subscriber.email).await {
+        Ok(false) => {}
+        Ok(true) => {
+            tracing::info!("Ignoring a subscription from a suppressed address");
+            return HttpResponse::Ok().finish();
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
     }
+
+    // The subscriber and its token are stored atomically, so a subscriber is never left
+    // without a way to confirm the subscription
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => {
+            match try_processing(&pool, idempotency_key, ANONYMOUS_USER_ID).await {
+                Ok(NextAction::StartProcessing(transaction)) => transaction,
+                Ok(NextAction::ReturnSavedResponse(saved_response)) => return saved_response,
+                Err(e) => {
+                    tracing::error!("Failed to process the idempotency key: {:?}", e);
+                    return HttpResponse::InternalServerError().finish();
+                }
+            }
+        }
+        None => match pool.begin().await {
+            Ok(transaction) => transaction,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+    };
+    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber).await {
+        Ok(Some(subscriber_id)) => subscriber_id,
+        // Already confirmed, answered like any other subscription
+        Ok(None) => return HttpResponse::Ok().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let subscription_token = SubscriptionToken::generate();
+    if store_token(&mut transaction, subscriber_id, &subscription_token)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    // Committed before the email goes out, the transaction doesn't hold a connection and the
+    // idempotency key while we wait on the provider. A subscriber whose email can't be sent
+    // stays pending, subscribing again sends a new link
+    let response = HttpResponse::Ok().finish();
+    let response = match &idempotency_key {
+        Some(idempotency_key) => {
+            match save_response(transaction, idempotency_key, ANONYMOUS_USER_ID, response).await {
+                Ok(response) => response,
+                Err(e) => {
+                    tracing::error!("Failed to save the response: {:?}", e);
+                    return HttpResponse::InternalServerError().finish();
+                }
+            }
+        }
+        None => match transaction.commit().await {
+            Ok(_) => response,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+    };
+
+    let email = new_subscriber.email.as_ref().to_owned();
+    let sent = match send_confirmation_email(
+        &email_client,
+        &email_templates,
+        new_subscriber,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    {
+        Ok(sent) => sent,
+        Err(_) => {
+            // The request failed after all, a retry with the same key has to send the email
+            if let Some(idempotency_key) = &idempotency_key {
+                if let Err(e) =
+                    discard_saved_response(&pool, idempotency_key, ANONYMOUS_USER_ID).await
+                {
+                    tracing::error!("Failed to discard the saved response: {:?}", e);
+                }
+            }
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    // The email is gone either way: failing to record it must not fail the request and get
+    // it sent again
+    if let Err(e) = record_sent_message(&**pool, &email, Some(subscriber_id), None, &sent).await {
+        tracing::error!("Failed to record a sent confirmation email: {:?}", e);
+    }
+    response
+}
+
+#[tracing::instrument(
+    name = "Sending a confirmation email to the new subscriber",
+    skip(
+        email_client,
+        email_templates,
+        new_subscriber,
+        base_url,
+        subscription_token
+    )
+)]
+pub async fn send_confirmation_email(
+    email_client: &EmailClient,
+    email_templates: &EmailTemplates,
+    new_subscriber: NewSubscriber,
+    base_url: &str,
+    subscription_token: &SubscriptionToken,
+) -> Result<SentMessage, EmailError> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url,
+        subscription_token.as_ref()
+    );
+    let body = email_templates.confirmation(new_subscriber.name.as_ref(), &confirmation_link);
+
+    email_client
+        .send_email_with_options(
+            new_subscriber.email,
+            "Welcome!",
+            &body.html,
+            &body.text,
+            None,
+            &EmailOptions {
+                message_stream: MessageStream::Transactional,
+                tag: Some("confirmation"),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to send the confirmation email: {:?}", e);
+            e
+        })
 }
 
+// Subscribing again restarts the confirmation of an address that isn't confirmed yet: a
+// pending subscriber asking for a new link, or someone who left the list, or whose
+// suppression was lifted, coming back. Returns None for confirmed subscribers
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
-    skip(new_subscriber, pool)
+    skip(transaction, new_subscriber)
 )]
 pub async fn insert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-    pool: &PgPool,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"INSERT INTO subscriptions (id, email, name, subscribed_at) VALUES ($1, $2, $3, $4)"#,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO UPDATE
+        SET name = EXCLUDED.name,
+            subscribed_at = EXCLUDED.subscribed_at,
+            status = 'pending_confirmation'
+        WHERE subscriptions.status <> 'confirmed'
+        RETURNING id"#,
         Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now()
     )
-    .execute(pool)
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(row.map(|r| r.id))
+}
+
+#[tracing::instrument(
+    name = "Storing the subscription token in the database",
+    skip(transaction, subscription_token)
+)]
+pub async fn store_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    subscription_token: &SubscriptionToken,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
+        VALUES ($1, $2)"#,
+        subscription_token.as_ref(),
+        subscriber_id
+    )
+    .execute(&mut **transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
@@ -0,0 +1,123 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::startup::HmacSecret;
+use crate::suppressions::{suppress_subscriber, SuppressionReason};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    subscriber_id: Uuid,
+    signature: String,
+}
+
+impl Parameters {
+    // The signature proves the link was generated by us, so nobody can unsubscribe
+    // somebody else just by guessing their id
+    fn verify(&self, secret: &HmacSecret) -> bool {
+        let signature = match hex::decode(&self.signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        let mut mac = signing_mac(&secret.0);
+        mac.update(self.subscriber_id.as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+fn signing_mac(secret: &Secret<String>) -> Hmac<sha2::Sha256> {
+    Hmac::<sha2::Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size.")
+}
+
+// Per-subscriber link that can be followed to stop receiving the newsletter
+pub fn unsubscribe_link(base_url: &str, subscriber_id: Uuid, secret: &Secret<String>) -> String {
+    let mut mac = signing_mac(secret);
+    mac.update(subscriber_id.as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+    format!(
+        "{}/subscriptions/unsubscribe?subscriber_id={}&signature={}",
+        base_url, subscriber_id, signature
+    )
+}
+
+// Following the link must not unsubscribe by itself: link scanners and prefetchers issue
+// GET requests on their own. The page asks for an explicit confirmation instead
+#[tracing::instrument(name = "Showing the unsubscribe page", skip(parameters, secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<Parameters>,
+    secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    if !parameters.verify(&secret) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <p>Click the button below to stop receiving our newsletter.</p>
+    <form action="/subscriptions/unsubscribe?subscriber_id={}&signature={}" method="post">
+        <input type="hidden" name="List-Unsubscribe" value="One-Click">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+            parameters.subscriber_id, parameters.signature
+        ))
+}
+
+// Target of both the unsubscribe page and the RFC 8058 one-click POST sent by mailbox providers
+#[tracing::instrument(
+    name = "Unsubscribing a subscriber",
+    skip(parameters, pool, secret),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    if !parameters.verify(&secret) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    // Unsubscribing twice is a no-op
+    match unsubscribe_subscriber(&pool, parameters.subscriber_id).await {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// The address is suppressed as well, so it can't be signed up again behind the owner's back
+#[tracing::instrument(name = "Marking subscriber as unsubscribed", skip(pool))]
+pub async fn unsubscribe_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1"#,
+        subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    suppress_subscriber(
+        &mut *transaction,
+        subscriber_id,
+        SuppressionReason::Unsubscribed,
+        "unsubscribe_link",
+    )
+    .await?;
+    transaction.commit().await
+}
@@ -0,0 +1,236 @@
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use sqlx::{PgPool, Postgres, Transaction};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::authentication::basic_authentication;
+use crate::configuration::WebhookSettings;
+use crate::sent_messages::{get_delivery, Delivery};
+use crate::suppressions::{suppress_email, SuppressionReason};
+
+const SECRET_HEADER: &str = "X-Postmark-Webhook-Secret";
+
+// Bounce types after which the address will never accept our emails
+const HARD_BOUNCE_TYPES: [&str; 2] = ["HardBounce", "BadEmailAddress"];
+
+// Postmark sends every kind of event to the same url, only some of them matter to us
+#[derive(serde::Deserialize)]
+#[serde(tag = "RecordType")]
+enum PostmarkEvent {
+    Bounce(BounceEvent),
+    SpamComplaint(BounceEvent),
+    #[serde(other)]
+    Other,
+}
+
+// Spam complaints are reported with the same shape as bounces
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BounceEvent {
+    #[serde(rename = "ID")]
+    id: Option<i64>,
+    #[serde(rename = "Type")]
+    bounce_type: String,
+    email: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    bounced_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "Receiving a Postmark webhook",
+    skip(request, payload, pool, settings),
+    fields(event_type=tracing::field::Empty, subscriber_id=tracing::field::Empty)
+)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    payload: web::Json<serde_json::Value>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<WebhookSettings>,
+) -> HttpResponse {
+    if !is_authorized(request.headers(), &settings) {
+        tracing::warn!("Rejected a Postmark webhook without valid credentials");
+        return HttpResponse::Unauthorized()
+            .insert_header((
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static(r#"Basic realm="webhooks""#),
+            ))
+            .finish();
+    }
+
+    let (event_type, event) = match serde_json::from_value(payload.0.clone()) {
+        Ok(PostmarkEvent::Bounce(event)) => ("bounce", event),
+        Ok(PostmarkEvent::SpamComplaint(event)) => ("spam_complaint", event),
+        // Acknowledged, otherwise Postmark keeps retrying
+        Ok(PostmarkEvent::Other) => return HttpResponse::Ok().finish(),
+        Err(e) => {
+            tracing::warn!("Failed to parse the Postmark webhook payload: {:?}", e);
+            return HttpResponse::BadRequest().finish();
+        }
+    };
+    tracing::Span::current().record("event_type", event_type);
+    // A complaint means the subscriber doesn't want our emails, whatever the reason
+    let suppression_reason = if event_type == "spam_complaint" {
+        Some(SuppressionReason::SpamComplaint)
+    } else if HARD_BOUNCE_TYPES.contains(&event.bounce_type.as_str()) {
+        Some(SuppressionReason::HardBounce)
+    } else {
+        None
+    };
+
+    let mut transaction = match pool.begin().await {
+        Ok(transaction) => transaction,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let delivery = match find_delivery(&mut transaction, &event).await {
+        Ok(delivery) => delivery,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let subscriber_id = delivery.subscriber_id;
+    if let Some(subscriber_id) = subscriber_id {
+        tracing::Span::current().record("subscriber_id", tracing::field::display(subscriber_id));
+    }
+    if insert_delivery_event(&mut transaction, &delivery, event_type, &event, &payload)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+    if let Some(reason) = suppression_reason {
+        // The address is suppressed even if it isn't on the list, so it can't join later
+        if suppress_email(&mut *transaction, &event.email, reason, "postmark_webhook")
+            .await
+            .is_err()
+        {
+            return HttpResponse::InternalServerError().finish();
+        }
+        if let Some(subscriber_id) = subscriber_id {
+            if mark_subscriber_as_suppressed(&mut transaction, subscriber_id)
+                .await
+                .is_err()
+            {
+                return HttpResponse::InternalServerError().finish();
+            }
+        }
+    }
+
+    match transaction.commit().await {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Either the basic auth credentials or the shared secret header must match
+fn is_authorized(headers: &HeaderMap, settings: &WebhookSettings) -> bool {
+    if let Some(secret) = headers.get(SECRET_HEADER) {
+        return constant_time_eq(secret.as_bytes(), settings.secret.expose_secret());
+    }
+    match basic_authentication(headers) {
+        Ok(credentials) => {
+            // Both are checked so the time taken doesn't tell which one was wrong
+            let username = constant_time_eq(credentials.username.as_bytes(), &settings.username);
+            let password = constant_time_eq(
+                credentials.password.expose_secret().as_bytes(),
+                settings.password.expose_secret(),
+            );
+            username & password
+        }
+        Err(_) => false,
+    }
+}
+
+fn constant_time_eq(provided: &[u8], expected: &str) -> bool {
+    provided.ct_eq(expected.as_bytes()).into()
+}
+
+// The message id tells exactly which email the event is about. Events about emails we
+// have no record of are matched to a subscriber by address, whatever its case
+#[tracing::instrument(name = "Looking up the delivery of an event", skip_all)]
+async fn find_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &BounceEvent,
+) -> Result<Delivery, sqlx::Error> {
+    if let Some(message_id) = &event.message_id {
+        if let Some(delivery) = get_delivery(&mut **transaction, message_id).await? {
+            return Ok(delivery);
+        }
+    }
+    // The same address may have been subscribed with different cases, the latest wins
+    let row = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE lower(email) = lower($1)
+        ORDER BY subscribed_at DESC
+        LIMIT 1
+        "#,
+        event.email
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(Delivery {
+        subscriber_id: row.map(|r| r.id),
+        newsletter_issue_id: None,
+    })
+}
+
+#[tracing::instrument(name = "Saving a delivery event", skip_all)]
+async fn insert_delivery_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    delivery: &Delivery,
+    event_type: &str,
+    event: &BounceEvent,
+    payload: &serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO delivery_events (
+            id, provider_event_id, subscriber_id, newsletter_issue_id, email, event_type,
+            bounce_type, message_id, payload, occurred_at, received_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, now())
+        ON CONFLICT (provider_event_id) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        event.id,
+        delivery.subscriber_id,
+        delivery.newsletter_issue_id,
+        event.email,
+        event_type,
+        event.bounce_type,
+        event.message_id,
+        payload,
+        event.bounced_at,
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Marking subscriber as suppressed", skip(transaction))]
+async fn mark_subscriber_as_suppressed(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = 'suppressed' WHERE id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
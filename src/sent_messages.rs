@@ -0,0 +1,72 @@
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::email_client::SentMessage;
+
+// Who an accepted email was meant for
+pub struct Delivery {
+    pub subscriber_id: Option<Uuid>,
+    pub newsletter_issue_id: Option<Uuid>,
+}
+
+#[tracing::instrument(
+    name = "Recording a sent message",
+    skip(executor, email, sent),
+    fields(message_id = ?sent.message_id)
+)]
+pub async fn record_sent_message(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+    subscriber_id: Option<Uuid>,
+    newsletter_issue_id: Option<Uuid>,
+    sent: &SentMessage,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO sent_messages (
+            id, message_id, subscriber_id, newsletter_issue_id, email, error_code, submitted_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (message_id) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        sent.message_id,
+        subscriber_id,
+        newsletter_issue_id,
+        email,
+        sent.error_code,
+        sent.submitted_at
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Looking up a sent message", skip(executor))]
+pub async fn get_delivery(
+    executor: impl PgExecutor<'_>,
+    message_id: &str,
+) -> Result<Option<Delivery>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT subscriber_id, newsletter_issue_id
+        FROM sent_messages
+        WHERE message_id = $1
+        "#,
+        message_id
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(row.map(|r| Delivery {
+        subscriber_id: r.subscriber_id,
+        newsletter_issue_id: r.newsletter_issue_id,
+    }))
+}
@@ -0,0 +1,104 @@
+mod in_memory;
+mod postgres;
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::session_state::TypedSession;
+
+pub use in_memory::InMemorySessionStore;
+pub use postgres::PostgresSessionStore;
+
+type SessionState = HashMap<String, String>;
+
+// Backend selected through the configuration. On top of the actix-session interface,
+// it can drop every session belonging to a user
+#[derive(Clone)]
+pub enum AppSessionStore {
+    Postgres(PostgresSessionStore),
+    InMemory(InMemorySessionStore),
+}
+
+impl AppSessionStore {
+    #[tracing::instrument(name = "Deleting every session of a user", skip(self))]
+    pub async fn delete_user_sessions(&self, user_id: Uuid) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Postgres(store) => store.delete_user_sessions(user_id).await,
+            Self::InMemory(store) => store.delete_user_sessions(user_id),
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for AppSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        match self {
+            Self::Postgres(store) => store.load(session_key).await,
+            Self::InMemory(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        match self {
+            Self::Postgres(store) => store.save(session_state, ttl).await,
+            Self::InMemory(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            Self::Postgres(store) => store.update(session_key, session_state, ttl).await,
+            Self::InMemory(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Postgres(store) => store.update_ttl(session_key, ttl).await,
+            Self::InMemory(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Postgres(store) => store.delete(session_key).await,
+            Self::InMemory(store) => store.delete(session_key).await,
+        }
+    }
+}
+
+// 64 alphanumeric characters, far too many to be guessed
+fn generate_session_key() -> SessionKey {
+    let mut rng = thread_rng();
+    let value: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(64)
+        .collect();
+    value
+        .try_into()
+        .expect("A 64 characters key is a valid key.")
+}
+
+// The owner of the session, if anybody is logged in
+fn user_id(session_state: &SessionState) -> Option<Uuid> {
+    session_state
+        .get(TypedSession::USER_ID_KEY)
+        .and_then(|user_id| serde_json::from_str(user_id).ok())
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use crate::session_store::{generate_session_key, user_id, SessionState};
+
+struct StoredSession {
+    state: SessionState,
+    user_id: Option<Uuid>,
+    expires_at: OffsetDateTime,
+}
+
+// Sessions are lost on restart and are not shared between instances, meant for tests
+#[derive(Clone, Default)]
+pub struct InMemorySessionStore {
+    sessions: Arc<RwLock<HashMap<String, StoredSession>>>,
+}
+
+impl InMemorySessionStore {
+    pub fn delete_user_sessions(&self, user_id: Uuid) -> Result<(), anyhow::Error> {
+        self.sessions
+            .write()
+            .map_err(|e| anyhow::anyhow!("{}", e))?
+            .retain(|_, session| session.user_id != Some(user_id));
+        Ok(())
+    }
+
+    fn insert(
+        &self,
+        session_key: &SessionKey,
+        state: SessionState,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        let session = StoredSession {
+            user_id: user_id(&state),
+            state,
+            expires_at: OffsetDateTime::now_utc() + *ttl,
+        };
+        self.sessions
+            .write()
+            .map_err(|e| anyhow::anyhow!("{}", e))?
+            .insert(session_key.as_ref().to_owned(), session);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for InMemorySessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let sessions = self
+            .sessions
+            .read()
+            .map_err(|e| LoadError::Other(anyhow::anyhow!("{}", e)))?;
+        Ok(sessions
+            .get(session_key.as_ref())
+            .filter(|session| session.expires_at > OffsetDateTime::now_utc())
+            .map(|session| session.state.clone()))
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        self.insert(&session_key, session_state, ttl)
+            .map_err(SaveError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let exists = self
+            .sessions
+            .read()
+            .map_err(|e| UpdateError::Other(anyhow::anyhow!("{}", e)))?
+            .contains_key(session_key.as_ref());
+
+        // The session was deleted while the request was in flight, start a new one
+        let session_key = if exists {
+            session_key
+        } else {
+            generate_session_key()
+        };
+        self.insert(&session_key, session_state, ttl)
+            .map_err(UpdateError::Other)?;
+        Ok(session_key)
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(session) = self
+            .sessions
+            .write()
+            .map_err(|e| anyhow::anyhow!("{}", e))?
+            .get_mut(session_key.as_ref())
+        {
+            session.expires_at = OffsetDateTime::now_utc() + *ttl;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.sessions
+            .write()
+            .map_err(|e| anyhow::anyhow!("{}", e))?
+            .remove(session_key.as_ref());
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::session_store::{generate_session_key, user_id, SessionState};
+
+#[derive(Clone)]
+pub struct PostgresSessionStore {
+    pool: PgPool,
+}
+
+impl PostgresSessionStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn delete_user_sessions(&self, user_id: Uuid) -> Result<(), anyhow::Error> {
+        sqlx::query!(r#"DELETE FROM sessions WHERE user_id = $1"#, user_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete the sessions of the user.")?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for PostgresSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>, LoadError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT session_state FROM sessions
+            WHERE session_key = $1 AND expires_at > now()
+            "#,
+            session_key.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load the session state.")
+        .map_err(LoadError::Other)?;
+
+        match row {
+            Some(row) => serde_json::from_value(row.session_state)
+                .context("Failed to deserialize the session state.")
+                .map(Some)
+                .map_err(LoadError::Deserialization),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = generate_session_key();
+        let user_id = user_id(&session_state);
+        let session_state = serde_json::to_value(session_state)
+            .context("Failed to serialize the session state.")
+            .map_err(SaveError::Serialization)?;
+
+        // Expired sessions are never loaded again, clean them up while we are at it
+        sqlx::query!(r#"DELETE FROM sessions WHERE expires_at <= now()"#)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete expired sessions.")
+            .map_err(SaveError::Other)?;
+        sqlx::query!(
+            r#"
+            INSERT INTO sessions (session_key, session_state, user_id, expires_at)
+            VALUES ($1, $2, $3, now() + make_interval(secs => $4))
+            "#,
+            session_key.as_ref(),
+            session_state,
+            user_id,
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save the session state.")
+        .map_err(SaveError::Other)?;
+
+        Ok(session_key)
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: SessionState,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let user_id = user_id(&session_state);
+        let serialized_state = serde_json::to_value(&session_state)
+            .context("Failed to serialize the session state.")
+            .map_err(UpdateError::Serialization)?;
+
+        let n_updated_rows = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET session_state = $2, user_id = $3, expires_at = now() + make_interval(secs => $4)
+            WHERE session_key = $1
+            "#,
+            session_key.as_ref(),
+            serialized_state,
+            user_id,
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update the session state.")
+        .map_err(UpdateError::Other)?
+        .rows_affected();
+
+        // The session was deleted while the request was in flight, start a new one
+        if n_updated_rows == 0 {
+            return self
+                .save(session_state, ttl)
+                .await
+                .map_err(|e| UpdateError::Other(anyhow::anyhow!("{}", e)));
+        }
+        Ok(session_key)
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE sessions SET expires_at = now() + make_interval(secs => $2)
+            WHERE session_key = $1
+            "#,
+            session_key.as_ref(),
+            ttl.as_seconds_f64()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update the session TTL.")?;
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+            r#"DELETE FROM sessions WHERE session_key = $1"#,
+            session_key.as_ref()
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to delete the session.")?;
+        Ok(())
+    }
+}
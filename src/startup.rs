@@ -1,38 +1,99 @@
-use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
-use crate::routes::{health_check::health_check, subscriptions::subscribe};
+use crate::authentication::RejectAnonymousUsers;
+use crate::clock::{Clock, SystemClock};
+use crate::configuration::{DatabaseSettings, SessionStoreType, Settings, WebhookSettings};
+use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    report_rate_limiter_metrics_until_stopped, EmailClient, MAX_MESSAGE_SIZE,
+};
+use crate::email_templates::EmailTemplates;
+use crate::issue_delivery_worker::run_worker_until_stopped;
+use crate::issue_scheduler::run_scheduler_until_stopped;
+use crate::routes::{
+    admin::{
+        add_suppression, add_suppression_api, admin_dashboard, approve_newsletter,
+        cancel_scheduled_newsletter, change_password, change_password_form, edit_newsletter,
+        list_newsletter_revisions, list_suppressions_api, log_out, preview_newsletter,
+        remove_suppression, remove_suppression_api, reopen_newsletter, reschedule_newsletter,
+        send_newsletter, send_test_newsletter, submit_newsletter, suppressions_page,
+    },
+    health_check::health_check,
+    login::{login, login_form},
+    newsletters::{create_newsletter, upload_newsletter_image},
+    subscriptions::subscribe,
+    subscriptions_confirm::confirm,
+    subscriptions_unsubscribe::{unsubscribe, unsubscribe_form},
+    webhooks::postmark_webhook,
+};
+use crate::session_store::{AppSessionStore, InMemorySessionStore, PostgresSessionStore};
+use crate::telemetry::UserIdRootSpanBuilder;
+use actix_session::SessionMiddleware;
+use actix_web::cookie::Key;
 use actix_web::dev::Server;
 use actix_web::web::Data;
 use actix_web::{web, App, HttpServer};
+use actix_web_flash_messages::storage::CookieMessageStore;
+use actix_web_flash_messages::FlashMessagesFramework;
+use chrono_tz::Tz;
+use secrecy::Secret;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
+use std::future::Future;
 use std::net::TcpListener;
+use std::pin::Pin;
+use std::sync::Arc;
 use tracing_actix_web::TracingLogger;
 
+type Worker = Pin<Box<dyn Future<Output = Result<(), std::io::Error>> + Send>>;
+
 pub struct Application {
     port: u16,
     server: Server,
+    // Background tasks running alongside the server: one delivers newsletter issues, one
+    // publishes the scheduled ones when their time comes, the last reports on email throttling
+    worker: Worker,
+    scheduler: Worker,
+    metrics_reporter: Worker,
 }
 
 impl Application {
-    pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
-        let connection_pool = connection_pool(&configuration.database);
+    pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
+        Self::build_with_clock(configuration, Arc::new(SystemClock)).await
+    }
 
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-        let base_url =
-            reqwest::Url::parse(&configuration.email_client.base_url).unwrap_or_else(|_| {
-                panic!("Can't parse {} as url", configuration.email_client.base_url)
-            });
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
+    // Everything that depends on the current time reads it from `clock`
+    pub async fn build_with_clock(
+        configuration: Settings,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, anyhow::Error> {
+        // Broken templates are caught now rather than when the first email goes out
+        let email_templates = Data::new(EmailTemplates::load(
+            &configuration.application.templates_directory,
+        )?);
+        let staff_emails = configuration
+            .application
+            .staff_emails()
+            .map_err(anyhow::Error::msg)?;
+        let session_key = configuration
+            .application
+            .session_key()
+            .map_err(anyhow::Error::msg)?;
+        let connection_pool = connection_pool(&configuration.database);
+        let webhook_settings = configuration.email_client.webhook.clone();
+        // Shared between the actix workers and the delivery worker
+        let email_client = Data::new(
+            configuration
+                .email_client
+                .client()
+                .map_err(anyhow::Error::msg)?,
         );
+        let session_store = match configuration.application.session_store {
+            SessionStoreType::Postgres => {
+                AppSessionStore::Postgres(PostgresSessionStore::new(connection_pool.clone()))
+            }
+            SessionStoreType::InMemory => {
+                AppSessionStore::InMemory(InMemorySessionStore::default())
+            }
+        };
 
         let address = format!(
             "{}:{}",
@@ -40,38 +101,190 @@ impl Application {
         );
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
-        let server = run(listener, connection_pool, email_client)?;
+        let server = run(
+            listener,
+            connection_pool.clone(),
+            email_client.clone(),
+            email_templates.clone(),
+            configuration.application.base_url.clone(),
+            configuration.application.hmac_secret.clone(),
+            session_key,
+            session_store,
+            webhook_settings,
+            staff_emails,
+            configuration.application.timezone,
+            clock.clone(),
+        )?;
+        let scheduler = Box::pin(run_scheduler_until_stopped(connection_pool.clone(), clock));
+        let metrics_reporter = Box::pin(report_rate_limiter_metrics_until_stopped(
+            email_client.clone(),
+        ));
+        let worker = Box::pin(run_worker_until_stopped(
+            connection_pool,
+            email_client,
+            email_templates,
+            configuration.application.base_url,
+            configuration.application.hmac_secret,
+        ));
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            worker,
+            scheduler,
+            metrics_reporter,
+        })
     }
 
     pub fn get_port(&self) -> u16 {
         self.port
     }
 
+    // Returns as soon as the server or any of the background tasks stops
     pub async fn run(self) -> Result<(), std::io::Error> {
-        self.server.await
+        tokio::select! {
+            outcome = self.server => outcome,
+            outcome = self.worker => outcome,
+            outcome = self.scheduler => outcome,
+            outcome = self.metrics_reporter => outcome,
+        }
     }
 }
 
+// Wrapper type so the base url can be retrieved from the app data by type
+pub struct ApplicationBaseUrl(pub String);
+
+pub struct HmacSecret(pub Secret<String>);
+
+// Recipients of the test sends of newsletter issues
+pub struct StaffEmails(pub Vec<SubscriberEmail>);
+
+pub struct ListTimezone(pub Tz);
+
 pub fn connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new().connect_lazy_with(configuration.connect_with_db())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
+    email_client: Data<EmailClient>,
+    email_templates: Data<EmailTemplates>,
+    base_url: String,
+    hmac_secret: Secret<String>,
+    // Signs the session and flash message cookies
+    session_key: Key,
+    session_store: AppSessionStore,
+    webhook_settings: WebhookSettings,
+    staff_emails: Vec<SubscriberEmail>,
+    timezone: Tz,
+    clock: Arc<dyn Clock>,
 ) -> Result<Server, std::io::Error> {
+    let message_store = CookieMessageStore::builder(session_key.clone()).build();
+    let message_framework = FlashMessagesFramework::builder(message_store).build();
     let connection_pool = Data::new(db_pool);
-    let email_client = Data::new(email_client);
+    let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let hmac_secret = Data::new(HmacSecret(hmac_secret));
+    // Handlers need the store too, to drop sessions on behalf of a user
+    let session_store_data = Data::new(session_store.clone());
+    let webhook_settings = Data::new(webhook_settings);
+    let staff_emails = Data::new(StaffEmails(staff_emails));
+    let timezone = Data::new(ListTimezone(timezone));
+    let clock: Data<dyn Clock> = Data::from(clock);
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(TracingLogger::default())
+            .wrap(message_framework.clone())
+            .wrap(SessionMiddleware::new(
+                session_store.clone(),
+                session_key.clone(),
+            ))
+            .wrap(TracingLogger::<UserIdRootSpanBuilder>::new())
             .route("/health_check", web::get().to(health_check))
+            .route("/login", web::get().to(login_form))
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/admin")
+                    .wrap(RejectAnonymousUsers)
+                    .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/password", web::get().to(change_password_form))
+                    .route("/password", web::post().to(change_password))
+                    .route("/logout", web::post().to(log_out))
+                    .route(
+                        "/newsletters/{newsletter_issue_id}",
+                        web::put().to(edit_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/revisions",
+                        web::get().to(list_newsletter_revisions),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/submit",
+                        web::post().to(submit_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/approve",
+                        web::post().to(approve_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/reopen",
+                        web::post().to(reopen_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/preview",
+                        web::get().to(preview_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/test",
+                        web::post().to(send_test_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/schedule",
+                        web::put().to(reschedule_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/schedule",
+                        web::delete().to(cancel_scheduled_newsletter),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/send",
+                        web::post().to(send_newsletter),
+                    )
+                    .route("/suppressions", web::get().to(suppressions_page))
+                    .route("/suppressions", web::post().to(add_suppression))
+                    .route("/suppressions/remove", web::post().to(remove_suppression))
+                    .route("/api/suppressions", web::get().to(list_suppressions_api))
+                    .route("/api/suppressions", web::post().to(add_suppression_api))
+                    .route(
+                        "/api/suppressions/{email}",
+                        web::delete().to(remove_suppression_api),
+                    ),
+            )
             .route("/subscriptions", web::post().to(subscribe))
+            .route("/newsletters", web::post().to(create_newsletter))
+            // Base64 encoded images are much larger than the default limit of the Json extractor
+            .service(
+                web::resource("/newsletters/images")
+                    .app_data(web::JsonConfig::default().limit(2 * MAX_MESSAGE_SIZE))
+                    .route(web::post().to(upload_newsletter_image)),
+            )
+            .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/webhooks/postmark", web::post().to(postmark_webhook))
             .app_data(connection_pool.clone())
             .app_data(email_client.clone())
+            .app_data(email_templates.clone())
+            .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(session_store_data.clone())
+            .app_data(webhook_settings.clone())
+            .app_data(staff_emails.clone())
+            .app_data(timezone.clone())
+            .app_data(clock.clone())
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    Unsubscribed,
+    HardBounce,
+    SpamComplaint,
+    // Blocked by an admin
+    Manual,
+}
+
+impl SuppressionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unsubscribed => "unsubscribed",
+            Self::HardBounce => "hard_bounce",
+            Self::SpamComplaint => "spam_complaint",
+            Self::Manual => "manual",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "unsubscribed" => Ok(Self::Unsubscribed),
+            "hard_bounce" => Ok(Self::HardBounce),
+            "spam_complaint" => Ok(Self::SpamComplaint),
+            "manual" => Ok(Self::Manual),
+            other => Err(format!("{} is not a valid suppression reason.", other)),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Suppression {
+    pub email: String,
+    pub reason: SuppressionReason,
+    // What put the address on the list, e.g. the unsubscribe link or an admin
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Addresses are compared lowercased, the list can't be dodged by changing the case
+#[tracing::instrument(name = "Checking the suppression list", skip(executor, email))]
+pub async fn is_suppressed(
+    executor: impl PgExecutor<'_>,
+    email: &SubscriberEmail,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM suppressions WHERE email = lower($1)) AS "suppressed!""#,
+        email.as_ref()
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(row.suppressed)
+}
+
+// Returns false if the address was already suppressed, the original entry is kept
+#[tracing::instrument(name = "Suppressing an email address", skip(executor, email))]
+pub async fn suppress_email(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+    reason: SuppressionReason,
+    source: &str,
+) -> Result<bool, sqlx::Error> {
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email, reason, source, created_at)
+        VALUES (lower($1), $2, $3, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        email,
+        reason.as_str(),
+        source
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    .rows_affected();
+    Ok(n_inserted_rows > 0)
+}
+
+#[tracing::instrument(name = "Suppressing a subscriber", skip(executor))]
+pub async fn suppress_subscriber(
+    executor: impl PgExecutor<'_>,
+    subscriber_id: Uuid,
+    reason: SuppressionReason,
+    source: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressions (email, reason, source, created_at)
+        SELECT lower(email), $2, $3, now() FROM subscriptions WHERE id = $1
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        reason.as_str(),
+        source
+    )
+    .execute(executor)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+// Returns false if the address wasn't suppressed
+#[tracing::instrument(name = "Removing a suppression", skip(pool, email))]
+pub async fn remove_suppression(pool: &PgPool, email: &str) -> Result<bool, sqlx::Error> {
+    let n_deleted_rows = sqlx::query!(r#"DELETE FROM suppressions WHERE email = lower($1)"#, email)
+        .execute(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?
+        .rows_affected();
+    Ok(n_deleted_rows > 0)
+}
+
+#[tracing::instrument(name = "Listing suppressions", skip(pool))]
+pub async fn list_suppressions(pool: &PgPool) -> Result<Vec<Suppression>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT email, reason, source, created_at FROM suppressions ORDER BY created_at DESC"#
+    )
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|row| {
+            Ok(Suppression {
+                reason: SuppressionReason::parse(&row.reason).map_err(anyhow::Error::msg)?,
+                email: row.email,
+                source: row.source,
+                created_at: row.created_at,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+
+    use super::SuppressionReason;
+
+    #[test]
+    fn every_reason_round_trips_through_its_string() {
+        for reason in [
+            SuppressionReason::Unsubscribed,
+            SuppressionReason::HardBounce,
+            SuppressionReason::SpamComplaint,
+            SuppressionReason::Manual,
+        ] {
+            assert_ok_eq!(SuppressionReason::parse(reason.as_str()), reason);
+        }
+    }
+
+    #[test]
+    fn unknown_reasons_are_rejected() {
+        assert_err!(SuppressionReason::parse("bored"));
+    }
+}
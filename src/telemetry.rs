@@ -1,5 +1,9 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use tokio::task::JoinHandle;
 use tracing::subscriber;
 use tracing::Subscriber;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
 use tracing_subscriber::fmt::MakeWriter;
@@ -32,3 +36,31 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     // Defines the subscriber used to process spans
     subscriber::set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+// CPU intensive work would block the async executor, so it is moved to the blocking pool.
+// The current span is carried over so the work shows up under the request that triggered it
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+// Same root span as the default one, plus the id of the user performing the request.
+// It's filled in once the request has been authenticated
+pub struct UserIdRootSpanBuilder;
+
+impl RootSpanBuilder for UserIdRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> tracing::Span {
+        tracing_actix_web::root_span!(request, user_id = tracing::field::Empty)
+    }
+
+    fn on_request_end<B: MessageBody>(
+        span: tracing::Span,
+        outcome: &Result<ServiceResponse<B>, actix_web::Error>,
+    ) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
@@ -0,0 +1,9 @@
+use actix_web::http::header::LOCATION;
+use actix_web::HttpResponse;
+
+// Redirects after a form submission, the browser follows up with a GET
+pub fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, location))
+        .finish()
+}
@@ -0,0 +1,79 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_access_the_admin_dashboard() {
+    let app = spawn_app().await;
+
+    let response = app.get_admin_dashboard().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn logout_clears_session_state() {
+    let app = spawn_app().await;
+
+    let response = app.login_test_user().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let response = app.post_logout().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(r#"<p><i>You have successfully logged out.</i></p>"#));
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn anonymous_api_clients_are_rejected_with_a_401_on_every_admin_route() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (reqwest::Method::GET, "/admin/dashboard"),
+        (reqwest::Method::GET, "/admin/password"),
+        (reqwest::Method::POST, "/admin/password"),
+        (reqwest::Method::POST, "/admin/logout"),
+    ];
+
+    for (method, path) in test_cases {
+        let response = app
+            .api_client
+            .request(method.clone(), format!("{}{}", &app.address, path))
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "{} {} did not reject an anonymous API client.",
+            method,
+            path
+        );
+    }
+}
+
+#[tokio::test]
+async fn anonymous_browsers_are_redirected_to_login_on_every_admin_route() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (reqwest::Method::GET, "/admin/dashboard"),
+        (reqwest::Method::GET, "/admin/password"),
+        (reqwest::Method::POST, "/admin/password"),
+        (reqwest::Method::POST, "/admin/logout"),
+    ];
+
+    for (method, path) in test_cases {
+        let response = app
+            .api_client
+            .request(method, format!("{}{}", &app.address, path))
+            .header("Accept", "text/html,application/xhtml+xml")
+            .send()
+            .await
+            .expect("Failed to execute request");
+
+        assert_is_redirect_to(&response, "/login");
+    }
+}
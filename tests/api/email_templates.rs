@@ -0,0 +1,100 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::get_configuration;
+use zero2prod::startup::Application;
+
+use crate::helpers::spawn_app;
+
+async fn sent_email_body(app: &crate::helpers::TestApp) -> serde_json::Value {
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    serde_json::from_slice(&email_request.body).unwrap()
+}
+
+#[tokio::test]
+async fn confirmation_emails_escape_the_subscriber_name_in_html() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=Tom%20%26%20Jerry&email=tom_and_jerry%40gmail.com".into())
+        .await;
+
+    let body = sent_email_body(&app).await;
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("Hi Tom &amp; Jerry,"));
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Hi Tom & Jerry,"));
+}
+
+#[tokio::test]
+async fn newsletters_are_wrapped_in_the_newsletter_template() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let body = sent_email_body(&app).await;
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("Hi le guin,</p>"));
+    assert!(html.contains("<p>Newsletter body as HTML</p>"));
+    assert!(html.contains("/subscriptions/unsubscribe?subscriber_id="));
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.contains("Hi le guin,"));
+    assert!(text.contains("Newsletter body as plain text"));
+    assert!(text.contains("/subscriptions/unsubscribe?subscriber_id="));
+}
+
+#[tokio::test]
+async fn the_application_does_not_start_with_invalid_templates() {
+    let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    std::fs::create_dir_all(&directory).unwrap();
+    for file in ["confirmation.html", "newsletter.html", "newsletter.txt"] {
+        std::fs::copy(format!("templates/{}", file), directory.join(file)).unwrap();
+    }
+    // Typo in the variable name
+    std::fs::write(
+        directory.join("confirmation.txt"),
+        "Hi {{nmae}}, visit {{confirm_url}} to confirm your subscription.",
+    )
+    .unwrap();
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.application.port = 0;
+    configuration.application.templates_directory = directory.to_string_lossy().into_owned();
+
+    let outcome = Application::build(configuration).await;
+
+    assert!(outcome.is_err());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
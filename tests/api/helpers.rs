@@ -1,13 +1,96 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::authentication::compute_password_hash;
+use zero2prod::clock::Clock;
+use zero2prod::configuration::{
+    get_configuration, DatabaseSettings, EmailProvider, SessionStoreType, WebhookSettings,
+};
+use zero2prod::email_client::EmailClient;
+use zero2prod::email_templates::EmailTemplates;
+use zero2prod::issue_delivery_worker::{
+    mark_delivered_issues, try_execute_task, ExecutionOutcome, IssueCache,
+};
+use zero2prod::issue_scheduler::publish_due_issues;
+use zero2prod::routes::subscriptions_unsubscribe::unsubscribe_link;
 use zero2prod::startup::{connection_pool, Application};
 use zero2prod::telemetry;
 
+// Stands in for the system clock, tests move it instead of waiting
+pub struct TestClock(Mutex<DateTime<Utc>>);
+
+impl TestClock {
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let password_hash = compute_password_hash(Secret::new(self.password.clone()))
+            .expect("Failed to hash the test user password.");
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash.expose_secret(),
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
+}
+
+// Confirmation links embedded in the body of the email sent to a new subscriber
+pub struct ConfirmationLinks {
+    pub html: reqwest::Url,
+    pub plain_text: reqwest::Url,
+}
+
 pub struct TestApp {
     pub address: String,
+    pub port: u16,
     pub db_pool: PgPool,
+    // Stands in for the Postmark API
+    pub email_server: MockServer,
+    pub hmac_secret: Secret<String>,
+    pub email_client: EmailClient,
+    pub email_templates: EmailTemplates,
+    pub webhook_settings: WebhookSettings,
+    // Shared with the application
+    pub clock: Arc<TestClock>,
+    pub test_user: TestUser,
+    // A second admin, issues can't be approved by their author
+    pub reviewer: TestUser,
+    // Keeps cookies between requests and does not follow redirects, like a browser session
+    // we can inspect
+    pub api_client: reqwest::Client,
 }
 
 impl TestApp {
@@ -20,6 +103,452 @@ impl TestApp {
             .await
             .expect("Failed to execute request")
     }
+
+    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+        let get_link = |s: &str| {
+            let links: Vec<_> = linkify::LinkFinder::new()
+                .links(s)
+                .filter(|l| *l.kind() == linkify::LinkKind::Url)
+                .collect();
+            assert_eq!(links.len(), 1);
+            let raw_link = links[0].as_str().to_owned();
+            let mut confirmation_link = reqwest::Url::parse(&raw_link).unwrap();
+            // Make sure we never call a live API
+            assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
+            // The base url does not know about the random port picked by the OS
+            confirmation_link.set_port(Some(self.port)).unwrap();
+            confirmation_link
+        };
+
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
+        ConfirmationLinks { html, plain_text }
+    }
+
+    pub async fn post_subscriptions_with_idempotency_key(
+        &self,
+        body: String,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Idempotency-Key", idempotency_key)
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_newsletters_with_idempotency_key(
+        &self,
+        body: serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn login_test_user(&self) -> reqwest::Response {
+        self.post_login(&serde_json::json!({
+            "username": &self.test_user.username,
+            "password": &self.test_user.password,
+        }))
+        .await
+    }
+
+    pub async fn get_login_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_admin_dashboard(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/dashboard", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin_dashboard_html(&self) -> String {
+        self.get_admin_dashboard().await.text().await.unwrap()
+    }
+
+    pub async fn get_change_password(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/password", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_change_password_html(&self) -> String {
+        self.get_change_password().await.text().await.unwrap()
+    }
+
+    pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/password", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_logout(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/logout", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_suppressions_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/suppressions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_suppression(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/suppressions", &self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_remove_suppression(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/suppressions/remove", &self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_newsletter_preview(
+        &self,
+        newsletter_issue_id: Uuid,
+        query: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}/preview",
+                &self.address, newsletter_issue_id
+            ))
+            .query(query)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_newsletter_test(
+        &self,
+        newsletter_issue_id: Uuid,
+        query: &[(&str, &str)],
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/test",
+                &self.address, newsletter_issue_id
+            ))
+            .query(query)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn put_newsletter_schedule(
+        &self,
+        newsletter_issue_id: Uuid,
+        scheduled_at: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .put(format!(
+                "{}/admin/newsletters/{}/schedule",
+                &self.address, newsletter_issue_id
+            ))
+            .json(&serde_json::json!({ "scheduled_at": scheduled_at }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn delete_newsletter_schedule(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .delete(format!(
+                "{}/admin/newsletters/{}/schedule",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // Creates an issue and takes it through review, sending it right away
+    pub async fn publish_newsletter(&self, body: serde_json::Value) -> Uuid {
+        let response = self.post_newsletters(body).await;
+        assert_eq!(202, response.status().as_u16());
+        let newsletter_issue_id = newsletter_issue_id(response).await;
+        self.approve_and_send_newsletter(newsletter_issue_id).await;
+        newsletter_issue_id
+    }
+
+    // The test user submits the issue and the reviewer approves and sends it, each in a
+    // session of their own so that `api_client` is left as it was
+    pub async fn approve_and_send_newsletter(&self, newsletter_issue_id: Uuid) {
+        self.approve_newsletter(newsletter_issue_id).await;
+        let reviewer = self.logged_in_client(&self.reviewer).await;
+        let response = self
+            .post_newsletter_action(&reviewer, newsletter_issue_id, "send")
+            .await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    pub async fn approve_newsletter(&self, newsletter_issue_id: Uuid) {
+        let author = self.logged_in_client(&self.test_user).await;
+        let response = self
+            .post_newsletter_action(&author, newsletter_issue_id, "submit")
+            .await;
+        assert_eq!(200, response.status().as_u16());
+        let reviewer = self.logged_in_client(&self.reviewer).await;
+        let response = self
+            .post_newsletter_action(&reviewer, newsletter_issue_id, "approve")
+            .await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    // `action` is one of submit, approve, reopen or send
+    pub async fn post_newsletter_action(
+        &self,
+        client: &reqwest::Client,
+        newsletter_issue_id: Uuid,
+        action: &str,
+    ) -> reqwest::Response {
+        client
+            .post(format!(
+                "{}/admin/newsletters/{}/{}",
+                &self.address, newsletter_issue_id, action
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn put_newsletter(
+        &self,
+        client: &reqwest::Client,
+        newsletter_issue_id: Uuid,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        client
+            .put(format!(
+                "{}/admin/newsletters/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_newsletter_revisions(
+        &self,
+        client: &reqwest::Client,
+        newsletter_issue_id: Uuid,
+    ) -> reqwest::Response {
+        client
+            .get(format!(
+                "{}/admin/newsletters/{}/revisions",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // A browser session of its own, logged in as the given user
+    pub async fn logged_in_client(&self, user: &TestUser) -> reqwest::Client {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .build()
+            .unwrap();
+        let response = client
+            .post(format!("{}/login", &self.address))
+            .form(&serde_json::json!({
+                "username": &user.username,
+                "password": &user.password,
+            }))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_is_redirect_to(&response, "/admin/dashboard");
+        client
+    }
+
+    pub async fn post_newsletter_image(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters/images", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // Authenticated the way Postmark does when the credentials are part of the webhook url
+    pub async fn post_postmark_webhook(&self, body: &serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .basic_auth(
+                &self.webhook_settings.username,
+                Some(self.webhook_settings.password.expose_secret()),
+            )
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    // Goes through the public API, so the subscriber ends up in the same state as a real one
+    pub async fn create_unconfirmed_subscriber(
+        &self,
+        name: &str,
+        email: &str,
+    ) -> ConfirmationLinks {
+        let body = serde_urlencoded::to_string([("name", name), ("email", email)]).unwrap();
+
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create unconfirmed subscriber")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        self.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        self.get_confirmation_links(&email_request)
+    }
+
+    pub async fn create_confirmed_subscriber(&self, name: &str, email: &str) -> Uuid {
+        let confirmation_links = self.create_unconfirmed_subscriber(name, email).await;
+        reqwest::get(confirmation_links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+
+        sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+            .fetch_one(&self.db_pool)
+            .await
+            .expect("Failed to fetch saved subscription.")
+            .id
+    }
+
+    // The application worker runs in the background as well. Tasks it is holding are skipped
+    // by our own dequeue, so we wait until the queue has been fully drained by either of us.
+    // Delivered issues are then marked as sent, as the worker does once it finds the queue empty
+    pub async fn dispatch_all_pending_emails(&self) {
+        let mut issue_cache = IssueCache::default();
+        loop {
+            let outcome = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.email_templates,
+                "http://127.0.0.1",
+                &self.hmac_secret,
+                &mut issue_cache,
+            )
+            .await
+            .unwrap();
+            if let ExecutionOutcome::EmptyQueue = outcome {
+                let pending = sqlx::query!(
+                    "SELECT COUNT(*) AS count FROM issue_delivery_queue WHERE execute_after <= now()"
+                )
+                .fetch_one(&self.db_pool)
+                .await
+                .unwrap()
+                .count
+                .unwrap();
+                if pending == 0 {
+                    mark_delivered_issues(&self.db_pool).await.unwrap();
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        }
+    }
+
+    // The application scheduler runs on the same clock, whichever of us gets to an issue
+    // first publishes it
+    pub async fn publish_scheduled_issues(&self) {
+        publish_due_issues(&self.db_pool, self.clock.as_ref())
+            .await
+            .unwrap();
+    }
+
+    pub fn unsubscribe_link(&self, subscriber_id: Uuid) -> reqwest::Url {
+        let raw_link = unsubscribe_link("http://127.0.0.1", subscriber_id, &self.hmac_secret);
+        let mut link = reqwest::Url::parse(&raw_link).unwrap();
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
 }
 
 // This value is initialized only in the first access
@@ -63,27 +592,78 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with_session_store(SessionStoreType::InMemory).await
+}
+
+pub async fn spawn_app_with_session_store(session_store: SessionStoreType) -> TestApp {
     Lazy::force(&TRACING);
 
+    let email_server = MockServer::start().await;
+
     let configuration = {
         let mut config = get_configuration().expect("Failed to read configuration.");
         config.database.database_name = Uuid::new_v4().to_string();
         // Let the OS choose a random port
         config.application.port = 0;
+        config.email_client.provider = EmailProvider::Postmark;
+        config.email_client.base_url = email_server.uri();
+        // Retries are covered by the EmailClient tests, here every mocked failure counts
+        config.email_client.retry.max_attempts = 1;
+        config.application.session_store = session_store;
+        config.application.staff_emails = vec![String::from("editor@zero2prod.com")];
+        config.application.timezone = chrono_tz::Europe::Madrid;
         config
     };
 
     // Create and migrate the database
     configure_database(&configuration.database).await;
 
-    let application = Application::build(configuration.clone())
+    let clock = Arc::new(TestClock(Mutex::new(Utc::now())));
+    let application = Application::build_with_clock(configuration.clone(), clock.clone())
         .await
         .expect("Failed to build application.");
-    let address = format!("http://127.0.0.1:{}", application.get_port());
-    let _ = tokio::spawn(application.run());
+    let port = application.get_port();
+    let address = format!("http://127.0.0.1:{}", port);
+    tokio::spawn(application.run());
 
-    TestApp {
+    let test_app = TestApp {
         address,
+        port,
         db_pool: connection_pool(&configuration.database),
-    }
+        email_server,
+        hmac_secret: configuration.application.hmac_secret,
+        webhook_settings: configuration.email_client.webhook.clone(),
+        clock,
+        email_client: configuration
+            .email_client
+            .client()
+            .expect("Invalid email client settings."),
+        email_templates: EmailTemplates::load(&configuration.application.templates_directory)
+            .unwrap(),
+        test_user: TestUser::generate(),
+        reviewer: TestUser::generate(),
+        api_client: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .build()
+            .unwrap(),
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app.reviewer.store(&test_app.db_pool).await;
+
+    test_app
+}
+
+pub async fn newsletter_issue_id(response: reqwest::Response) -> Uuid {
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(response.headers().get("Location").unwrap(), location);
 }
@@ -0,0 +1,131 @@
+use secrecy::Secret;
+use zero2prod::authentication::{create_admin, NewPassword};
+use zero2prod::configuration::SessionStoreType;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, spawn_app_with_session_store};
+
+#[tokio::test]
+async fn an_error_flash_message_is_set_on_failure() {
+    let app = spawn_app().await;
+    let login_body = serde_json::json!({
+        "username": "random-username",
+        "password": "random-password"
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed.</i></p>"));
+
+    // The message is only shown once
+    let html_page = app.get_login_html().await;
+    assert!(!html_page.contains("Authentication failed."));
+}
+
+#[tokio::test]
+async fn redirect_to_admin_dashboard_after_login_success() {
+    let app = spawn_app().await;
+
+    let response = app.login_test_user().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn the_seeded_admin_cannot_log_in_with_its_published_password() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "admin",
+            "password": "everythinghastostartsomewhere"
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn admins_created_from_the_command_line_can_log_in() {
+    let app = spawn_app().await;
+    let password = "a very long and secret passphrase";
+
+    // The locked seeded admin gets a new password, like a new admin would
+    for username in ["admin", "editor"] {
+        create_admin(
+            username,
+            NewPassword::parse(Secret::new(password.to_owned())).unwrap(),
+            &app.db_pool,
+        )
+        .await
+        .unwrap();
+
+        let response = app
+            .post_login(&serde_json::json!({ "username": username, "password": password }))
+            .await;
+        assert_is_redirect_to(&response, "/admin/dashboard");
+    }
+}
+
+#[tokio::test]
+async fn the_session_id_is_rotated_on_login() {
+    let app = spawn_app().await;
+
+    let first_session_id = app
+        .login_test_user()
+        .await
+        .cookies()
+        .find(|c| c.name() == "id")
+        .expect("No session cookie was set.")
+        .value()
+        .to_string();
+    let second_session_id = app
+        .login_test_user()
+        .await
+        .cookies()
+        .find(|c| c.name() == "id")
+        .expect("No session cookie was set.")
+        .value()
+        .to_string();
+    assert_ne!(first_session_id, second_session_id);
+
+    // The previous session can't be used anymore
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{}/admin/dashboard", &app.address))
+        .header("Cookie", format!("id={}", first_session_id))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn sessions_are_persisted_in_postgres() {
+    let app = spawn_app_with_session_store(SessionStoreType::Postgres).await;
+
+    let response = app.login_test_user().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+
+    let session = sqlx::query!("SELECT user_id FROM sessions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the saved session.");
+    assert_eq!(session.user_id, Some(app.test_user.user_id));
+
+    app.post_logout().await;
+    let n_sessions = sqlx::query!("SELECT COUNT(*) AS count FROM sessions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+        .unwrap();
+    assert_eq!(n_sessions, 0);
+}
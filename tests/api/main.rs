@@ -1,3 +1,16 @@
+mod admin_dashboard;
+mod change_password;
+mod email_templates;
 mod health_check;
 mod helpers;
+mod login;
+mod newsletter_images;
+mod newsletter_preview;
+mod newsletter_workflow;
+mod newsletters;
+mod scheduled_newsletters;
 mod subscriptions;
+mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod suppressions;
+mod webhooks;
@@ -0,0 +1,157 @@
+use base64::Engine;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\nnot much of an image";
+
+fn image_request_body(content_type: &str) -> serde_json::Value {
+    serde_json::json!({
+        "name": "cover.png",
+        "content_type": content_type,
+        "content": base64::engine::general_purpose::STANDARD.encode(PNG),
+    })
+}
+
+async fn upload_image(app: &TestApp) -> (Uuid, String) {
+    let response = app
+        .post_newsletter_image(&image_request_body("image/png"))
+        .await;
+    assert_eq!(201, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    (
+        body["image_id"].as_str().unwrap().parse().unwrap(),
+        body["src"].as_str().unwrap().to_string(),
+    )
+}
+
+#[tokio::test]
+async fn uploaded_images_are_sent_inline_with_the_issue() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    let (image_id, src) = upload_image(&app).await;
+    assert_eq!(src, format!("cid:{}", image_id));
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": format!(r#"<p>Newsletter body as HTML</p><img src="{}">"#, src),
+        },
+        "images": [image_id],
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(
+        body["Attachments"],
+        serde_json::json!([{
+            "Name": "cover.png",
+            "Content": base64::engine::general_purpose::STANDARD.encode(PNG),
+            "ContentType": "image/png",
+            "ContentID": src,
+        }])
+    );
+}
+
+#[tokio::test]
+async fn issues_referencing_unknown_images_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "images": [Uuid::new_v4()],
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn image_uploads_return_400_for_invalid_data() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (image_request_body("application/pdf"), "not an image"),
+        (
+            serde_json::json!({
+                "name": "cover.png",
+                "content_type": "image/png",
+                "content": "definitely not base64!",
+            }),
+            "content not base64 encoded",
+        ),
+        (
+            serde_json::json!({
+                "name": " ",
+                "content_type": "image/png",
+                "content": "",
+            }),
+            "empty name",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_newsletter_image(&invalid_body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn images_too_large_to_be_emailed_are_rejected() {
+    let app = spawn_app().await;
+    // 7.5 MB are over 10 MB once base64 encoded, the way the email provider counts them
+    let content = vec![0u8; 7_900_000];
+
+    let response = app
+        .post_newsletter_image(&serde_json::json!({
+            "name": "cover.png",
+            "content_type": "image/png",
+            "content": base64::engine::general_purpose::STANDARD.encode(content),
+        }))
+        .await;
+
+    assert_eq!(413, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn image_uploads_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters/images", &app.address))
+        .json(&image_request_body("image/png"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
@@ -0,0 +1,212 @@
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+// Stored directly, publishing would queue deliveries for the subscribers
+async fn create_issue(app: &TestApp) -> Uuid {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, published_at
+        )
+        VALUES ($1, 'Newsletter title', 'Newsletter body as plain text',
+            '<p>Newsletter body as HTML</p>', now())
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    newsletter_issue_id
+}
+
+async fn queued_deliveries(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_preview_or_test_an_issue() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_issue(&app).await;
+
+    let response = app.get_newsletter_preview(newsletter_issue_id, &[]).await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_newsletter_test(newsletter_issue_id, &[]).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_preview_is_personalised_for_the_given_subscriber() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    let newsletter_issue_id = create_issue(&app).await;
+    app.login_test_user().await;
+
+    let subscriber_id_param = subscriber_id.to_string();
+    let response = app
+        .get_newsletter_preview(
+            newsletter_issue_id,
+            &[("subscriber_id", &subscriber_id_param)],
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Hi le guin,"));
+    assert!(html.contains("<p>Newsletter body as HTML</p>"));
+    // Staff must not get a working unsubscribe link for the subscriber
+    assert!(html.contains(&format!(
+        "/subscriptions/unsubscribe?subscriber_id={}",
+        Uuid::nil()
+    )));
+    assert!(!html.contains(&subscriber_id.to_string()));
+
+    let response = app
+        .get_newsletter_preview(
+            newsletter_issue_id,
+            &[("subscriber_id", &subscriber_id_param), ("format", "text")],
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let text = response.text().await.unwrap();
+    assert!(text.contains("Hi le guin,"));
+    assert!(text.contains("Newsletter body as plain text"));
+}
+
+#[tokio::test]
+async fn the_preview_inlines_the_issue_images() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_issue(&app).await;
+    let image_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_images (image_id, file_name, content_type, content, uploaded_at)
+        VALUES ($1, 'cover.png', 'image/png', '\x89504e47'::bytea, now())
+        "#,
+        image_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues SET html_content = $2 WHERE newsletter_issue_id = $1;
+        "#,
+        newsletter_issue_id,
+        format!(r#"<img src="cid:{}">"#, image_id)
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO newsletter_issue_images (newsletter_issue_id, image_id) VALUES ($1, $2)",
+        newsletter_issue_id,
+        image_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.login_test_user().await;
+
+    let html = app
+        .get_newsletter_preview(newsletter_issue_id, &[])
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html.contains(r#"<img src="data:image/png;base64,iVBORw==">"#));
+    assert!(html.contains("Hi there,"));
+}
+
+#[tokio::test]
+async fn previewing_an_unknown_issue_or_subscriber_returns_404() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_issue(&app).await;
+    app.login_test_user().await;
+
+    let response = app.get_newsletter_preview(Uuid::new_v4(), &[]).await;
+    assert_eq!(404, response.status().as_u16());
+
+    let response = app
+        .get_newsletter_preview(
+            newsletter_issue_id,
+            &[("subscriber_id", &Uuid::new_v4().to_string())],
+        )
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn test_sends_only_go_to_the_staff_addresses() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    let newsletter_issue_id = create_issue(&app).await;
+    app.login_test_user().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletter_test(
+            newsletter_issue_id,
+            &[("subscriber_id", &subscriber_id.to_string())],
+        )
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["recipients"],
+        serde_json::json!(["editor@zero2prod.com"])
+    );
+
+    // Preceded by the confirmation email of the subscriber
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["To"], "editor@zero2prod.com");
+    assert_eq!(email["Subject"], "[Test] Newsletter title");
+    let html = email["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("Hi le guin,"));
+    assert!(!html.contains(&subscriber_id.to_string()));
+    assert!(!email["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(&subscriber_id.to_string()));
+    assert_eq!(queued_deliveries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn test_sends_of_an_unknown_issue_return_404() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletter_test(Uuid::new_v4(), &[]).await;
+    assert_eq!(404, response.status().as_u16());
+}
@@ -0,0 +1,328 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, newsletter_issue_id, spawn_app, TestApp};
+
+fn issue_body(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn create_draft(app: &TestApp) -> Uuid {
+    let response = app.post_newsletters(issue_body("First draft")).await;
+    assert_eq!(202, response.status().as_u16());
+    newsletter_issue_id(response).await
+}
+
+async fn status(app: &TestApp, newsletter_issue_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+async fn revision_titles(app: &TestApp, newsletter_issue_id: Uuid) -> Vec<String> {
+    let client = app.logged_in_client(&app.test_user).await;
+    let response = app
+        .get_newsletter_revisions(&client, newsletter_issue_id)
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    let revisions: Vec<serde_json::Value> = response.json().await.unwrap();
+    revisions
+        .iter()
+        .map(|r| r["title"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[tokio::test]
+async fn new_issues_are_drafts_of_their_author() {
+    let app = spawn_app().await;
+
+    let newsletter_issue_id = create_draft(&app).await;
+
+    let issue = sqlx::query!(
+        "SELECT status, author_id, published_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.status, "draft");
+    assert_eq!(issue.author_id, Some(app.test_user.user_id));
+    assert_eq!(issue.published_at, None);
+
+    let client = app.logged_in_client(&app.test_user).await;
+    let response = app
+        .get_newsletter_revisions(&client, newsletter_issue_id)
+        .await;
+    let revisions: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(revisions[0]["revision"], 1);
+    assert_eq!(revisions[0]["edited_by"], app.test_user.username.as_str());
+}
+
+#[tokio::test]
+async fn authors_cannot_approve_their_own_issues() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_draft(&app).await;
+    let author = app.logged_in_client(&app.test_user).await;
+    app.post_newsletter_action(&author, newsletter_issue_id, "submit")
+        .await;
+
+    let response = app
+        .post_newsletter_action(&author, newsletter_issue_id, "approve")
+        .await;
+
+    assert_eq!(403, response.status().as_u16());
+    assert_eq!(status(&app, newsletter_issue_id).await, "in_review");
+}
+
+#[tokio::test]
+async fn admins_who_edited_an_issue_cannot_approve_it() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_draft(&app).await;
+    let author = app.logged_in_client(&app.test_user).await;
+    let reviewer = app.logged_in_client(&app.reviewer).await;
+    app.post_newsletter_action(&author, newsletter_issue_id, "submit")
+        .await;
+    // Asked for changes, and made some of them
+    app.post_newsletter_action(&reviewer, newsletter_issue_id, "reopen")
+        .await;
+    app.put_newsletter(&reviewer, newsletter_issue_id, &issue_body("Second draft"))
+        .await;
+    app.post_newsletter_action(&author, newsletter_issue_id, "submit")
+        .await;
+
+    let response = app
+        .post_newsletter_action(&reviewer, newsletter_issue_id, "approve")
+        .await;
+
+    assert_eq!(403, response.status().as_u16());
+    assert_eq!(status(&app, newsletter_issue_id).await, "in_review");
+}
+
+#[tokio::test]
+async fn reviewers_who_only_approved_an_issue_can_approve_its_next_version() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_draft(&app).await;
+    let author = app.logged_in_client(&app.test_user).await;
+    let reviewer = app.logged_in_client(&app.reviewer).await;
+    app.post_newsletter_action(&author, newsletter_issue_id, "submit")
+        .await;
+    app.post_newsletter_action(&reviewer, newsletter_issue_id, "approve")
+        .await;
+
+    app.post_newsletter_action(&author, newsletter_issue_id, "reopen")
+        .await;
+    app.put_newsletter(&author, newsletter_issue_id, &issue_body("Second draft"))
+        .await;
+    app.post_newsletter_action(&author, newsletter_issue_id, "submit")
+        .await;
+    let response = app
+        .post_newsletter_action(&reviewer, newsletter_issue_id, "approve")
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(status(&app, newsletter_issue_id).await, "approved");
+}
+
+#[tokio::test]
+async fn issues_cannot_skip_a_step_of_the_workflow() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    let newsletter_issue_id = create_draft(&app).await;
+    let reviewer = app.logged_in_client(&app.reviewer).await;
+    app.login_test_user().await;
+
+    let response = app
+        .post_newsletter_action(&reviewer, newsletter_issue_id, "approve")
+        .await;
+    assert_eq!(409, response.status().as_u16(), "Approved a draft.");
+    let response = app
+        .post_newsletter_action(&reviewer, newsletter_issue_id, "send")
+        .await;
+    assert_eq!(409, response.status().as_u16(), "Sent a draft.");
+    let response = app
+        .put_newsletter_schedule(newsletter_issue_id, "2100-01-01T08:00")
+        .await;
+    assert_eq!(409, response.status().as_u16(), "Scheduled a draft.");
+
+    assert_eq!(status(&app, newsletter_issue_id).await, "draft");
+    let queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(queued, 0);
+}
+
+#[tokio::test]
+async fn the_database_refuses_transitions_outside_the_workflow() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_draft(&app).await;
+
+    let result = sqlx::query!(
+        "UPDATE newsletter_issues SET status = 'sent' WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&app.db_pool)
+    .await;
+    assert!(result.is_err());
+
+    // Nor can the author be recorded as the approver
+    let result = sqlx::query!(
+        "UPDATE newsletter_issues SET approved_by = author_id WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&app.db_pool)
+    .await;
+    assert!(result.is_err());
+
+    // Nor anyone else who edited it
+    let author = app.logged_in_client(&app.test_user).await;
+    app.put_newsletter(
+        &app.logged_in_client(&app.reviewer).await,
+        newsletter_issue_id,
+        &issue_body("Second draft"),
+    )
+    .await;
+    app.post_newsletter_action(&author, newsletter_issue_id, "submit")
+        .await;
+    let result = sqlx::query!(
+        "UPDATE newsletter_issues SET approved_by = $2 WHERE newsletter_issue_id = $1",
+        newsletter_issue_id,
+        app.reviewer.user_id
+    )
+    .execute(&app.db_pool)
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn only_drafts_can_be_edited_and_every_edit_is_kept() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_draft(&app).await;
+    let author = app.logged_in_client(&app.test_user).await;
+
+    let response = app
+        .put_newsletter(&author, newsletter_issue_id, &issue_body("Second draft"))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    app.post_newsletter_action(&author, newsletter_issue_id, "submit")
+        .await;
+    let response = app
+        .put_newsletter(&author, newsletter_issue_id, &issue_body("Sneaky edit"))
+        .await;
+    assert_eq!(409, response.status().as_u16());
+
+    app.post_newsletter_action(&author, newsletter_issue_id, "reopen")
+        .await;
+    let response = app
+        .put_newsletter(&author, newsletter_issue_id, &issue_body("Third draft"))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    assert_eq!(
+        revision_titles(&app, newsletter_issue_id).await,
+        vec!["First draft", "Second draft", "Third draft"]
+    );
+    let issue = sqlx::query!(
+        "SELECT title FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.title, "Third draft");
+}
+
+#[tokio::test]
+async fn reopened_issues_need_a_new_approval() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_draft(&app).await;
+    app.approve_newsletter(newsletter_issue_id).await;
+    let author = app.logged_in_client(&app.test_user).await;
+
+    let response = app
+        .post_newsletter_action(&author, newsletter_issue_id, "reopen")
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let issue = sqlx::query!(
+        "SELECT status, approved_by FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.status, "draft");
+    assert_eq!(issue.approved_by, None);
+}
+
+#[tokio::test]
+async fn sent_issues_are_marked_as_sent_once_delivered() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_issue_id = app.publish_newsletter(issue_body("Newsletter title")).await;
+    let issue = sqlx::query!(
+        "SELECT approved_by FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.approved_by, Some(app.reviewer.user_id));
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(status(&app, newsletter_issue_id).await, "sent");
+}
+
+#[tokio::test]
+async fn revisions_of_unknown_issues_are_not_found() {
+    let app = spawn_app().await;
+    let client = app.logged_in_client(&app.test_user).await;
+
+    let response = app.get_newsletter_revisions(&client, Uuid::new_v4()).await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_review_or_edit_an_issue() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = create_draft(&app).await;
+
+    for action in ["submit", "approve", "reopen", "send"] {
+        let response = app
+            .post_newsletter_action(&app.api_client, newsletter_issue_id, action)
+            .await;
+        assert_is_redirect_to(&response, "/login");
+    }
+    let response = app
+        .put_newsletter(&app.api_client, newsletter_issue_id, &issue_body("Edit"))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .get_newsletter_revisions(&app.api_client, newsletter_issue_id)
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
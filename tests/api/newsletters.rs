@@ -0,0 +1,507 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{newsletter_issue_id, spawn_app};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    // Any request to Postmark makes the test fail on drop
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unsubscribed_subscribers() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    reqwest::Client::new()
+        .post(app.unsubscribe_link(subscriber_id))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_carry_the_subscriber_unsubscribe_link() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let list_unsubscribe = body["Headers"][0]["Value"].as_str().unwrap();
+    let mut expected_link = app.unsubscribe_link(subscriber_id);
+    // The link in the email is built from the base url, which has no port
+    expected_link.set_port(None).unwrap();
+    assert_eq!(list_unsubscribe, format!("<{}>", expected_link));
+}
+
+#[tokio::test]
+async fn newsletters_go_out_on_the_broadcast_stream() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["MessageStream"], "broadcast");
+    assert_eq!(body["Tag"], "newsletter");
+    assert_eq!(body["Metadata"]["subscriber_id"], subscriber_id.to_string());
+    assert!(body["Metadata"]["newsletter_issue_id"].is_string());
+}
+
+#[tokio::test]
+async fn delivered_issues_are_recorded_with_the_provider_message_id() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "To": "ursula_le_guin@gmail.com",
+            "SubmittedAt": "2024-02-16T10:00:00.1234567-05:00",
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "ErrorCode": 0,
+            "Message": "OK"
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT m.subscriber_id, m.email, m.error_code, m.submitted_at
+        FROM sent_messages m
+        JOIN newsletter_issues i ON i.newsletter_issue_id = m.newsletter_issue_id
+        WHERE m.message_id = 'b7bc2f4a-e38e-4336-af7d-e6c392c2f817'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("The delivery was not recorded.");
+    assert_eq!(saved.subscriber_id, Some(subscriber_id));
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.error_code, 0);
+    assert_eq!(
+        saved.submitted_at,
+        "2024-02-16T15:00:00.123456Z"
+            .parse::<chrono::DateTime<chrono::Utc>>()
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn issues_written_in_markdown_are_sent_as_html_and_text() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "markdown": "Read [the *news*](https://example.com).<script>alert(1)</script>",
+        }
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("<em>news</em></a>"));
+    assert!(html.contains("href=\"https://example.com\""));
+    assert!(!html.contains("<script>"));
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.contains("Read the news (https://example.com)."));
+
+    let issue = sqlx::query!("SELECT markdown_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        issue.markdown_content.as_deref(),
+        Some("Read [the *news*](https://example.com).<script>alert(1)</script>")
+    );
+}
+
+#[tokio::test]
+async fn newsletters_skip_subscribers_with_invalid_stored_emails() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    // Simulate an address accepted by older, looser validation rules
+    sqlx::query!(
+        r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'definitely-not-an-email', 'legacy', now(), 'confirmed')"#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_rescheduled_instead_of_lost() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!("SELECT n_retries, execute_after FROM issue_delivery_queue",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The failed delivery is no longer in the queue.");
+    assert_eq!(task.n_retries, 1);
+    assert!(task.execute_after > chrono::Utc::now());
+}
+
+#[tokio::test]
+async fn deliveries_refused_for_good_are_not_rescheduled() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+            "ErrorCode": 406,
+            "Message": "You tried to send to a recipient that has been marked as inactive."
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    let remaining_tasks = sqlx::query!("SELECT COUNT(*) AS count FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(remaining_tasks, Some(0));
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": {"html": "<p>Newsletter body as HTML</p>"}
+            }),
+            "missing text content",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": {
+                    "markdown": "Newsletter body as *Markdown*",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "both markdown and html content",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_newsletters(invalid_body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn issues_can_no_longer_be_scheduled_when_they_are_created() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "scheduled_at": "2100-01-01T08:00:00Z"
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["error"],
+        "Issues are scheduled once approved, not when they are created."
+    );
+    let stored = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(stored, 0);
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // A double-clicked publish button must not create the issue twice
+    let mut newsletter_issue_ids = Vec::new();
+    for _ in 0..2 {
+        let response = app
+            .post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key)
+            .await;
+        assert_eq!(202, response.status().as_u16());
+        newsletter_issue_ids.push(newsletter_issue_id(response).await);
+    }
+    assert_eq!(newsletter_issue_ids[0], newsletter_issue_ids[1]);
+    app.approve_and_send_newsletter(newsletter_issue_ids[0])
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let n_issues = sqlx::query!("SELECT COUNT(*) AS count FROM newsletter_issues",)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+        .unwrap();
+    assert_eq!(n_issues, 1);
+}
+
+#[tokio::test]
+async fn concurrent_newsletter_creation_is_handled_gracefully() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(100)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response1 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key);
+    let response2 =
+        app.post_newsletters_with_idempotency_key(newsletter_request_body(), &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    let issue_id = newsletter_issue_id(response1).await;
+    assert_eq!(issue_id, newsletter_issue_id(response2).await);
+    app.approve_and_send_newsletter(issue_id).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
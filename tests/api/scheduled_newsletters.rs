@@ -0,0 +1,211 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, newsletter_issue_id, spawn_app, TestApp};
+
+fn utc(s: &str) -> DateTime<Utc> {
+    s.parse().unwrap()
+}
+
+// Written and approved on a Friday
+async fn approved_issue(app: &TestApp) -> Uuid {
+    app.clock.set(utc("2030-03-01T16:00:00Z"));
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+        }))
+        .await;
+    assert_eq!(202, response.status().as_u16());
+    let newsletter_issue_id = newsletter_issue_id(response).await;
+    app.approve_newsletter(newsletter_issue_id).await;
+    newsletter_issue_id
+}
+
+// Going out on Monday at 08:00 in Madrid, 07:00 UTC. Scheduled from a session of its own,
+// the tests decide whether `api_client` is logged in
+async fn schedule_issue(app: &TestApp, scheduled_at: &str) -> Uuid {
+    let newsletter_issue_id = approved_issue(app).await;
+    let response = app
+        .logged_in_client(&app.test_user)
+        .await
+        .put(format!(
+            "{}/admin/newsletters/{}/schedule",
+            &app.address, newsletter_issue_id
+        ))
+        .json(&serde_json::json!({ "scheduled_at": scheduled_at }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, response.status().as_u16());
+    newsletter_issue_id
+}
+
+async fn queued_deliveries(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+async fn published_at(app: &TestApp, newsletter_issue_id: Uuid) -> Option<DateTime<Utc>> {
+    sqlx::query!(
+        "SELECT published_at FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .published_at
+}
+
+#[tokio::test]
+async fn scheduled_issues_go_out_when_their_time_comes_in_the_list_timezone() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    let newsletter_issue_id = schedule_issue(&app, "2030-03-04T08:00").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.clock.set(utc("2030-03-04T06:59:59Z"));
+    app.publish_scheduled_issues().await;
+    assert_eq!(queued_deliveries(&app).await, 0);
+    assert_eq!(published_at(&app, newsletter_issue_id).await, None);
+
+    app.clock.set(utc("2030-03-04T07:00:00Z"));
+    app.publish_scheduled_issues().await;
+    assert_eq!(
+        published_at(&app, newsletter_issue_id).await,
+        Some(utc("2030-03-04T07:00:00Z"))
+    );
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past_or_at_invalid_times() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = approved_issue(&app).await;
+    app.login_test_user().await;
+    let test_cases = vec![
+        ("2030-03-01T08:00", "a time in the past"),
+        ("2030-03-31T02:30", "a time skipped by the clock change"),
+        ("next monday", "a malformed time"),
+    ];
+
+    for (scheduled_at, description) in test_cases {
+        let response = app
+            .put_newsletter_schedule(newsletter_issue_id, scheduled_at)
+            .await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when scheduling at {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn rescheduled_issues_go_out_at_their_new_time() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = schedule_issue(&app, "2030-03-04T08:00").await;
+    app.login_test_user().await;
+
+    let response = app
+        .put_newsletter_schedule(newsletter_issue_id, "2030-03-05T08:00")
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    app.clock.set(utc("2030-03-04T07:00:00Z"));
+    app.publish_scheduled_issues().await;
+    assert_eq!(published_at(&app, newsletter_issue_id).await, None);
+
+    app.clock.set(utc("2030-03-05T07:00:00Z"));
+    app.publish_scheduled_issues().await;
+    assert!(published_at(&app, newsletter_issue_id).await.is_some());
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_not_published() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = schedule_issue(&app, "2030-03-04T08:00").await;
+    app.login_test_user().await;
+
+    let response = app.delete_newsletter_schedule(newsletter_issue_id).await;
+    assert_eq!(204, response.status().as_u16());
+
+    app.clock.set(utc("2030-03-05T07:00:00Z"));
+    app.publish_scheduled_issues().await;
+    assert_eq!(published_at(&app, newsletter_issue_id).await, None);
+
+    // Nothing left to cancel
+    let response = app.delete_newsletter_schedule(newsletter_issue_id).await;
+    assert_eq!(409, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "The issue is not scheduled.");
+
+    let response = app.delete_newsletter_schedule(Uuid::new_v4()).await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_rescheduled_or_cancelled() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = schedule_issue(&app, "2030-03-04T08:00").await;
+    app.clock.set(utc("2030-03-04T07:00:00Z"));
+    app.publish_scheduled_issues().await;
+    app.login_test_user().await;
+
+    let response = app
+        .put_newsletter_schedule(newsletter_issue_id, "2030-03-05T08:00")
+        .await;
+    assert_eq!(409, response.status().as_u16());
+
+    let response = app.delete_newsletter_schedule(newsletter_issue_id).await;
+    assert_eq!(409, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "The issue is already published.");
+}
+
+#[tokio::test]
+async fn rescheduling_requires_a_time_in_the_future() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = schedule_issue(&app, "2030-03-04T08:00").await;
+    app.login_test_user().await;
+
+    let response = app
+        .put_newsletter_schedule(newsletter_issue_id, "2030-02-28T08:00")
+        .await;
+    assert_eq!(400, response.status().as_u16());
+
+    let response = app
+        .put_newsletter_schedule(Uuid::new_v4(), "2030-03-05T08:00")
+        .await;
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_reschedule_or_cancel_an_issue() {
+    let app = spawn_app().await;
+    let newsletter_issue_id = schedule_issue(&app, "2030-03-04T08:00").await;
+
+    let response = app
+        .put_newsletter_schedule(newsletter_issue_id, "2030-03-05T08:00")
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.delete_newsletter_schedule(newsletter_issue_id).await;
+    assert_is_redirect_to(&response, "/login");
+}
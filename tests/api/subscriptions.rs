@@ -1,21 +1,287 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
 use crate::helpers::spawn_app;
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
     let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
 
     // Check that the response is the expected one
-    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
     let response = app.post_subscriptions(body).await;
     assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_persists_the_new_subscriber_as_pending_confirmation() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body).await;
 
     // Check that the result is actually saved in the database
-    let saved = sqlx::query!("SELECT email, name FROM subscriptions",)
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
         .fetch_one(&app.db_pool)
         .await
         .expect("Failed to fetch saved subscription.");
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribe_sends_a_confirmation_email_for_valid_data() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+
+    // The mock server verifies on drop that exactly one email has been sent
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body).await;
+}
+
+#[tokio::test]
+async fn confirmation_emails_go_out_on_the_transactional_stream() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["MessageStream"], "outbound");
+    assert_eq!(body["Tag"], "confirmation");
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_recorded_as_sent_messages() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "To": "ursula_le_guin@gmail.com",
+            "SubmittedAt": "2024-02-16T10:00:00.1234567-05:00",
+            "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+            "ErrorCode": 0,
+            "Message": "OK"
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body).await;
+
+    let saved = sqlx::query!(
+        r#"
+        SELECT m.message_id, m.newsletter_issue_id
+        FROM sent_messages m
+        JOIN subscriptions s ON s.id = m.subscriber_id
+        WHERE s.email = 'ursula_le_guin@gmail.com'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("The confirmation email was not recorded.");
+    assert_eq!(
+        saved.message_id.as_deref(),
+        Some("0a129aee-e1cd-480d-b08d-4f48548ff48d")
+    );
+    assert_eq!(saved.newsletter_issue_id, None);
+}
+
+#[tokio::test]
+async fn subscriptions_are_kept_when_the_sent_email_cannot_be_recorded() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+    // Taken already, recording the confirmation email fails
+    sqlx::query!(
+        r#"
+        INSERT INTO sent_messages (id, message_id, email, error_code, submitted_at)
+        VALUES ($1, '0a129aee-e1cd-480d-b08d-4f48548ff48d', 'someone@gmail.com', 0, now())
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "To": "ursula_le_guin@gmail.com",
+            "SubmittedAt": "2024-02-16T10:00:00.1234567-05:00",
+            "MessageID": "0a129aee-e1cd-480d-b08d-4f48548ff48d",
+            "ErrorCode": 0,
+            "Message": "OK"
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body).await;
+    assert_eq!(200, response.status().as_u16());
+
+    // The link in the email works
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_sends_a_confirmation_email_with_a_link() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Both bodies must point to the same confirmation link
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_500_if_the_confirmation_email_cannot_be_sent() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscriptions(body).await;
+    assert_eq!(500, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribing_again_while_pending_sends_a_new_confirmation_email() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..2 {
+        let response = app.post_subscriptions(body.clone()).await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_sent_another_confirmation() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(String::from(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        ))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_people_can_subscribe_again_once_their_suppression_is_lifted() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    reqwest::Client::new()
+        .post(app.unsubscribe_link(subscriber_id))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("DELETE FROM suppressions")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(String::from(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        ))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT id, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.id, subscriber_id);
+    assert_eq!(saved.status, "pending_confirmation");
 }
 
 #[tokio::test]
@@ -68,3 +334,119 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
         );
     }
 }
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_an_invalid_idempotency_key() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+
+    let response = app
+        .post_subscriptions_with_idempotency_key(body, &"a".repeat(50))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_is_idempotent() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Retrying must not fail on the already stored email, nor send a second email
+    for _ in 0..2 {
+        let response = app
+            .post_subscriptions_with_idempotency_key(body.clone(), &idempotency_key)
+            .await;
+        assert_eq!(200, response.status().as_u16());
+    }
+}
+
+#[tokio::test]
+async fn visitors_reusing_an_idempotency_key_do_not_get_each_other_responses() {
+    let app = spawn_app().await;
+    let idempotency_key = "subscribe";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for body in [
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "name=tolkien&email=jrr_tolkien%40gmail.com",
+    ] {
+        let response = app
+            .post_subscriptions_with_idempotency_key(body.into(), idempotency_key)
+            .await;
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    let saved = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 2);
+}
+
+#[tokio::test]
+async fn concurrent_subscribe_requests_are_handled_gracefully() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Slow email delivery keeps the first request in flight while the second one arrives
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(100)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response1 = app.post_subscriptions_with_idempotency_key(body.clone(), &idempotency_key);
+    let response2 = app.post_subscriptions_with_idempotency_key(body, &idempotency_key);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(200, response1.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_can_be_retried_with_the_same_key_after_a_failure() {
+    let app = spawn_app().await;
+    let body = String::from("name=le%20guin&email=ursula_le_guin%40gmail.com");
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Failures are not saved, so the retry is processed from scratch
+    let response = app
+        .post_subscriptions_with_idempotency_key(body.clone(), &idempotency_key)
+        .await;
+    assert_eq!(500, response.status().as_u16());
+    let response = app
+        .post_subscriptions_with_idempotency_key(body, &idempotency_key)
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
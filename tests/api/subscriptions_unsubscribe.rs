@@ -0,0 +1,125 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn unsubscribe_requests_without_parameters_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let url = format!("{}/subscriptions/unsubscribe", app.address);
+
+    let get_response = client.get(&url).send().await.unwrap();
+    let post_response = client.post(&url).send().await.unwrap();
+
+    assert_eq!(400, get_response.status().as_u16());
+    assert_eq!(400, post_response.status().as_u16());
+}
+
+#[tokio::test]
+async fn unsubscribe_requests_with_an_invalid_signature_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    let client = reqwest::Client::new();
+    let test_cases = vec![
+        ("not-hex", "not hex encoded"),
+        ("abcdef0123", "signed with another key"),
+    ];
+
+    for (signature, description) in test_cases {
+        let url = format!(
+            "{}/subscriptions/unsubscribe?subscriber_id={}&signature={}",
+            app.address, subscriber_id, signature
+        );
+        let get_response = client.get(&url).send().await.unwrap();
+        let post_response = client.post(&url).send().await.unwrap();
+
+        assert_eq!(
+            401,
+            get_response.status().as_u16(),
+            "The unsubscribe page did not return a 401 when the signature was {}.",
+            description
+        );
+        assert_eq!(
+            401,
+            post_response.status().as_u16(),
+            "The unsubscribe endpoint did not return a 401 when the signature was {}.",
+            description
+        );
+    }
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn the_unsubscribe_page_asks_for_confirmation_without_unsubscribing() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    let response = reqwest::get(app.unsubscribe_link(subscriber_id))
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"method="post""#));
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_marks_the_subscriber_as_unsubscribed() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    // Mailbox providers send exactly this body, as mandated by RFC 8058
+    let response = reqwest::Client::new()
+        .post(app.unsubscribe_link(subscriber_id))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribing_twice_is_idempotent() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    let client = reqwest::Client::new();
+
+    for _ in 0..2 {
+        let response = client
+            .post(app.unsubscribe_link(subscriber_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(200, response.status().as_u16());
+    }
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions",)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "unsubscribed");
+}
@@ -0,0 +1,203 @@
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+async fn suppressed_emails(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!("SELECT email, reason FROM suppressions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.email, r.reason))
+        .collect()
+}
+
+async fn suppress(app: &TestApp, email: &str) {
+    sqlx::query!(
+        "INSERT INTO suppressions (email, reason, source, created_at) VALUES ($1, 'manual', 'test', now())",
+        email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn suppressed_addresses_cannot_subscribe_again() {
+    let app = spawn_app().await;
+    suppress(&app, "ursula_le_guin@gmail.com").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Same answer as a successful subscription, we don't disclose who is on the list
+    let body = "name=le%20guin&email=Ursula_Le_Guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn unsubscribing_adds_the_address_to_the_suppression_list() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    reqwest::Client::new()
+        .post(app.unsubscribe_link(subscriber_id))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    assert_eq!(
+        suppressed_emails(&app).await,
+        vec![("ursula_le_guin@gmail.com".into(), "unsubscribed".into())]
+    );
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_suppressed_addresses() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    suppress(&app, "ursula_le_guin@gmail.com").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.publish_newsletter(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn admins_can_add_and_remove_suppressions() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    let response = app.post_suppression("ursula_le_guin@gmail.com").await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("<p><i>ursula_le_guin@gmail.com has been suppressed.</i></p>"));
+    assert!(html_page.contains(&format!("admin:{}", app.test_user.username)));
+    assert_eq!(
+        suppressed_emails(&app).await,
+        vec![("ursula_le_guin@gmail.com".into(), "manual".into())]
+    );
+
+    let response = app
+        .post_remove_suppression("ursula_le_guin@gmail.com")
+        .await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("<p><i>ursula_le_guin@gmail.com is no longer suppressed.</i></p>"));
+    assert!(suppressed_emails(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn invalid_addresses_are_not_suppressed() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    let response = app.post_suppression("definitely-not-an-email").await;
+
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("definitely-not-an-email is an invalid email."));
+    assert!(suppressed_emails(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn the_suppression_list_can_be_managed_through_the_api() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+    let url = format!("{}/admin/api/suppressions", &app.address);
+
+    let response = app
+        .api_client
+        .post(&url)
+        .json(&serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(201, response.status().as_u16());
+
+    let response = app
+        .api_client
+        .post(&url)
+        .json(&serde_json::json!({ "email": "ursula_le_guin@gmail.com" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(409, response.status().as_u16());
+
+    let list: serde_json::Value = app
+        .api_client
+        .get(&url)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(list[0]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(list[0]["reason"], "manual");
+
+    let delete_url = format!("{}/ursula_le_guin@gmail.com", url);
+    let response = app.api_client.delete(&delete_url).send().await.unwrap();
+    assert_eq!(204, response.status().as_u16());
+    let response = app.api_client.delete(&delete_url).send().await.unwrap();
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_api_rejects_invalid_addresses_with_a_400() {
+    let app = spawn_app().await;
+    app.login_test_user().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/admin/api/suppressions", &app.address))
+        .json(&serde_json::json!({ "email": "definitely-not-an-email" }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn anonymous_api_clients_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/api/suppressions", &app.address))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(401, response.status().as_u16());
+}
@@ -0,0 +1,304 @@
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, TestApp};
+
+fn bounce(bounce_type: &str, email: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": rand::random::<u32>(),
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "Name": "Hard bounce",
+        "MessageID": Uuid::new_v4().to_string(),
+        "MessageStream": "outbound",
+        "Description": "The server was unable to deliver your message.",
+        "Details": "smtp;550 5.1.1 The email account that you tried to reach does not exist.",
+        "Email": email,
+        "From": "newsletter@example.com",
+        "BouncedAt": "2024-01-26T10:00:00.1234567Z",
+        "Inactive": true,
+        "CanActivate": true,
+        "Subject": "Newsletter title"
+    })
+}
+
+fn spam_complaint(email: &str) -> serde_json::Value {
+    let mut complaint = bounce("SpamComplaint", email);
+    complaint["RecordType"] = "SpamComplaint".into();
+    complaint
+}
+
+async fn subscriber_status(app: &TestApp, subscriber_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+async fn recorded_events(app: &TestApp) -> Vec<(Option<Uuid>, String)> {
+    sqlx::query!("SELECT subscriber_id, event_type FROM delivery_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.subscriber_id, r.event_type))
+        .collect()
+}
+
+#[tokio::test]
+async fn webhooks_without_valid_credentials_are_rejected() {
+    let app = spawn_app().await;
+    let body = bounce("HardBounce", "ursula_le_guin@gmail.com");
+    let client = reqwest::Client::new();
+    let url = format!("{}/webhooks/postmark", &app.address);
+    let test_cases = vec![
+        (client.post(&url), "no credentials"),
+        (
+            client
+                .post(&url)
+                .basic_auth(&app.webhook_settings.username, Some("wrong-password")),
+            "wrong password",
+        ),
+        (
+            client.post(&url).basic_auth(
+                "wrong-username",
+                Some(app.webhook_settings.password.expose_secret()),
+            ),
+            "wrong username",
+        ),
+        (
+            client
+                .post(&url)
+                .header("X-Postmark-Webhook-Secret", "wrong-secret"),
+            "wrong shared secret",
+        ),
+    ];
+
+    for (request, description) in test_cases {
+        let response = request.json(&body).send().await.unwrap();
+
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "The webhook was not rejected with {}.",
+            description
+        );
+    }
+    assert!(recorded_events(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn webhooks_authenticated_with_the_shared_secret_are_accepted() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .header(
+            "X-Postmark-Webhook-Secret",
+            app.webhook_settings.secret.expose_secret(),
+        )
+        .json(&bounce("SoftBounce", "ursula_le_guin@gmail.com"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(recorded_events(&app).await.len(), 1);
+}
+
+#[tokio::test]
+async fn a_hard_bounce_suppresses_the_subscriber() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    let response = app
+        .post_postmark_webhook(&bounce("HardBounce", "ursula_le_guin@gmail.com"))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "suppressed");
+    assert_eq!(
+        recorded_events(&app).await,
+        vec![(Some(subscriber_id), String::from("bounce"))]
+    );
+}
+
+#[tokio::test]
+async fn events_are_matched_to_the_subscriber_whatever_the_case_of_the_address() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "Ursula_Le_Guin@gmail.com")
+        .await;
+
+    let response = app
+        .post_postmark_webhook(&bounce("HardBounce", "ursula_le_guin@GMAIL.com"))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "suppressed");
+    assert_eq!(
+        recorded_events(&app).await,
+        vec![(Some(subscriber_id), String::from("bounce"))]
+    );
+}
+
+#[tokio::test]
+async fn a_spam_complaint_suppresses_the_subscriber() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    let response = app
+        .post_postmark_webhook(&spam_complaint("ursula_le_guin@gmail.com"))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "suppressed");
+    assert_eq!(
+        recorded_events(&app).await,
+        vec![(Some(subscriber_id), String::from("spam_complaint"))]
+    );
+}
+
+#[tokio::test]
+async fn events_are_linked_to_the_delivery_their_message_id_refers_to() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    let issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, published_at
+        )
+        VALUES ($1, 'Newsletter title', 'text', '<p>html</p>', now())
+        "#,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let message_id = Uuid::new_v4().to_string();
+    sqlx::query!(
+        r#"
+        INSERT INTO sent_messages (
+            id, message_id, subscriber_id, newsletter_issue_id, email, error_code, submitted_at
+        )
+        VALUES ($1, $2, $3, $4, 'ursula_le_guin@gmail.com', 0, now())
+        "#,
+        Uuid::new_v4(),
+        message_id,
+        subscriber_id,
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Providers may report the address with a different case than the one we have
+    let mut body = bounce("SoftBounce", "Ursula_Le_Guin@gmail.com");
+    body["MessageID"] = message_id.into();
+    app.post_postmark_webhook(&body).await;
+
+    let event = sqlx::query!("SELECT subscriber_id, newsletter_issue_id FROM delivery_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(event.subscriber_id, Some(subscriber_id));
+    assert_eq!(event.newsletter_issue_id, Some(issue_id));
+}
+
+#[tokio::test]
+async fn a_soft_bounce_is_recorded_without_suppressing_the_subscriber() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    app.post_postmark_webhook(&bounce("SoftBounce", "ursula_le_guin@gmail.com"))
+        .await;
+
+    assert_eq!(subscriber_status(&app, subscriber_id).await, "confirmed");
+    assert_eq!(recorded_events(&app).await.len(), 1);
+}
+
+#[tokio::test]
+async fn redelivered_webhooks_are_recorded_once() {
+    let app = spawn_app().await;
+    let body = bounce("HardBounce", "ursula_le_guin@gmail.com");
+
+    app.post_postmark_webhook(&body).await;
+    let response = app.post_postmark_webhook(&body).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(recorded_events(&app).await.len(), 1);
+}
+
+#[tokio::test]
+async fn other_record_types_are_acknowledged_and_ignored() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Delivery",
+            "MessageID": Uuid::new_v4().to_string(),
+            "Recipient": "ursula_le_guin@gmail.com",
+            "DeliveredAt": "2024-01-26T10:00:00Z"
+        }))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(recorded_events(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn malformed_bounces_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Bounce",
+            "Email": "ursula_le_guin@gmail.com"
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn suppressed_subscribers_no_longer_receive_newsletters() {
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.post_postmark_webhook(&spam_complaint("ursula_le_guin@gmail.com"))
+        .await;
+
+    app.publish_newsletter(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await;
+
+    let queued = sqlx::query!(
+        "SELECT COUNT(*) AS count FROM issue_delivery_queue WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+    assert_eq!(queued, Some(0));
+}